@@ -0,0 +1,234 @@
+use crate::downloader::generate_download_link;
+use crate::helpers::{fetch_manifest, FileList};
+use crate::install::{
+    install_relative_path, list_files, move_file, remove_empty_parents, state_dir, vanilla_dir,
+    write_atomic,
+};
+use crate::prompt::{confirm, PromptKey};
+use crate::receipt::{load_receipt, save_receipt, unix_time};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupAction {
+    /// Move the files under `.er-downloader/quarantine/` so they can be put back by hand.
+    Quarantine,
+    Delete,
+}
+
+/// One cleanup pass, appended to `.er-downloader/cleanup.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CleanupRecord {
+    pub version: String,
+    pub action: CleanupAction,
+    pub performed_at: u64,
+    pub files: Vec<String>,
+    /// Where quarantined files were moved, relative to the install directory.
+    pub quarantine: Option<String>,
+}
+
+fn cleanup_log_path(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("cleanup.json")
+}
+
+fn quarantine_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("quarantine")
+}
+
+pub async fn fetch_file_list(
+    list: &FileList,
+) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let download = generate_download_link(&list.path).await?;
+    let data = reqwest::get(&download.link).await?.bytes().await?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if !actual.eq_ignore_ascii_case(&list.sha256) {
+        return Err(format!(
+            "Integrity check failed for {}: expected sha256 {}, got {}",
+            list.path, list.sha256, actual
+        )
+        .into());
+    }
+    let paths: Vec<String> = serde_json::from_slice(&data)?;
+    paths
+        .iter()
+        .map(|path| install_relative_path(path))
+        .collect()
+}
+
+/// Files the downloader installed that the installed version no longer ships.
+pub async fn find_orphaned_files(
+    install_dir: &Path,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let Some(receipt) = load_receipt(install_dir)? else {
+        return Err("No Convergence install was found in this directory".into());
+    };
+    let manifest = fetch_manifest().await?.for_channel(receipt.channel)?;
+    let Some(list) = manifest.file_lists.get(&receipt.installed_version()?) else {
+        return Err(format!(
+            "The update manifest has no file list for {}",
+            receipt.version
+        )
+        .into());
+    };
+    let owned = fetch_file_list(list).await?;
+    // The `.new` copies conflicts saved next to the player's edited files aren't leftovers
+    let is_orphan = |path: &str| {
+        !owned.contains(path)
+            && !path
+                .strip_suffix(".new")
+                .is_some_and(|original| owned.contains(original))
+    };
+
+    if receipt.complete {
+        return Ok(receipt
+            .files
+            .keys()
+            .filter(|path| is_orphan(path))
+            .cloned()
+            .collect());
+    }
+
+    // Installs migrated from version.txt don't know all their files, so look through the
+    // version's own folders instead
+    let roots: BTreeSet<&str> = owned
+        .iter()
+        .filter_map(|path| path.split_once('/').map(|(root, _)| root))
+        .collect();
+    let mut orphans = Vec::new();
+    for root in roots {
+        let dir = install_dir.join(root);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in list_files(&dir)? {
+            let path = format!("{}/{}", root, file);
+            if is_orphan(&path) {
+                orphans.push(path);
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+/// Quarantines or deletes `files`, drops them from the receipt and records what was done.
+pub fn clean_orphaned_files(
+    install_dir: &Path,
+    files: &[String],
+    action: CleanupAction,
+) -> Result<CleanupRecord, Box<dyn std::error::Error>> {
+    let Some(mut receipt) = load_receipt(install_dir)? else {
+        return Err("No Convergence install was found in this directory".into());
+    };
+    let performed_at = unix_time();
+    let quarantine = quarantine_dir(install_dir).join(performed_at.to_string());
+    let mut cleaned = Vec::new();
+    for path in files {
+        let path = install_relative_path(path)?;
+        let target = install_dir.join(&path);
+        if target.exists() {
+            match action {
+                CleanupAction::Quarantine => move_file(&target, &quarantine.join(&path))?,
+                CleanupAction::Delete => fs::remove_file(&target)?,
+            }
+            // Put back the game's own copy if the mod had replaced one
+            let original = vanilla_dir(install_dir).join(&path);
+            if original.exists() {
+                move_file(&original, &target)?;
+            } else {
+                remove_empty_parents(install_dir, &target);
+            }
+        }
+        receipt.files.remove(&path);
+        cleaned.push(path);
+    }
+    save_receipt(install_dir, &receipt)?;
+
+    let record = CleanupRecord {
+        version: receipt.version,
+        action,
+        performed_at,
+        files: cleaned,
+        quarantine: (action == CleanupAction::Quarantine).then(|| {
+            quarantine
+                .strip_prefix(install_dir)
+                .unwrap_or(&quarantine)
+                .to_string_lossy()
+                .to_string()
+        }),
+    };
+    let mut log: Vec<CleanupRecord> = fs::read(cleanup_log_path(install_dir))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    log.push(record.clone());
+    write_atomic(
+        &cleanup_log_path(install_dir),
+        &serde_json::to_vec_pretty(&log)?,
+    )?;
+    println!(
+        "Cleaned up {} orphaned file(s) ({:?})",
+        record.files.len(),
+        record.action
+    );
+    Ok(record)
+}
+
+/// Looks for files left behind by older versions and asks whether to quarantine or
+/// delete them. Failures are only logged, the update itself already succeeded.
+pub async fn offer_orphan_cleanup(install_dir: &Path) {
+    let orphans = match find_orphaned_files(install_dir).await {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            eprintln!("Skipping orphaned file cleanup: {}", e);
+            return;
+        }
+    };
+    if orphans.is_empty() {
+        return;
+    }
+
+    const SHOWN: usize = 10;
+    let mut message = format!(
+        "{} file(s) from older versions are no longer part of Convergence:\n",
+        orphans.len()
+    );
+    for path in orphans.iter().take(SHOWN) {
+        message.push_str(&format!("\n{}", path));
+    }
+    if orphans.len() > SHOWN {
+        message.push_str(&format!("\n...and {} more", orphans.len() - SHOWN));
+    }
+    let clean = confirm(
+        PromptKey::OrphanCleanup,
+        "Leftover files",
+        &message,
+        "Clean up",
+        "Leave them",
+    )
+    .await;
+    if !clean {
+        return;
+    }
+    let quarantine = confirm(
+        PromptKey::OrphanQuarantine,
+        "Leftover files",
+        "Move the files to a quarantine folder, or delete them permanently?",
+        "Quarantine",
+        "Delete",
+    )
+    .await;
+    let action = if quarantine {
+        CleanupAction::Quarantine
+    } else {
+        CleanupAction::Delete
+    };
+    if let Err(e) = clean_orphaned_files(install_dir, &orphans, action) {
+        eprintln!("Orphaned file cleanup failed: {}", e);
+    }
+}
@@ -0,0 +1,405 @@
+//! The `er-downloader-cli` binary: installs and updates Convergence without the window,
+//! through the same update logic as the app.
+
+use crate::core::{set_core, Core};
+use crate::downloader::{
+    apply_update_plan, install_full_mod, install_version, set_download_control, DownloadCancelled,
+    DownloadControl, DOWNLOAD_CONTROL,
+};
+use crate::events::{Progress, ProgressSink};
+use crate::helpers::{check_updates, format_size, UpdatePlan, UpdateStatus};
+use crate::install::{list_snapshots, recover_interrupted_update};
+use crate::prompt::{BoxFuture, Confirmation, HeadlessPrompter, Prompter};
+use crate::receipt::load_receipt;
+use crate::settings::load_settings;
+use crate::verify::{repair_installation, verify_installation, VerifyReport};
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+};
+
+/// Exit codes scripts can rely on.
+pub const EXIT_OK: u8 = 0;
+pub const EXIT_ERROR: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+/// `check`: an update, migration or channel switch is waiting.
+pub const EXIT_UPDATE_AVAILABLE: u8 = 3;
+pub const EXIT_NOT_INSTALLED: u8 = 4;
+/// `verify`: files are missing or modified.
+pub const EXIT_VERIFY_FAILED: u8 = 5;
+/// A prompt was declined or the download was cancelled.
+pub const EXIT_CANCELLED: u8 = 6;
+
+const USAGE: &str = "Usage: er-downloader-cli <command> [options] <directory>
+
+Commands:
+  check                  Show whether an update is available
+  install [--version V]  Install the latest release, or version V
+          [--pin]        Stay on V until the pin is removed
+  update                 Update an existing install
+  verify [--repair]      Check the installed files, re-downloading broken ones with --repair
+  status                 Show the installed version and settings without going online
+
+Options:
+  --non-interactive      Never ask; answer prompts from ER_DOWNLOADER_ANSWERS
+                         (e.g. migrate=no,orphan_cleanup=yes) or their defaults
+
+Exit codes:
+  0 success or up to date, 1 error, 2 invalid usage, 3 update available,
+  4 not installed, 5 verification failed, 6 declined or cancelled";
+
+enum Command {
+    Check,
+    Install { version: Option<String>, pin: bool },
+    Update,
+    Verify { repair: bool },
+    Status,
+}
+
+struct Args {
+    command: Command,
+    directory: String,
+    interactive: bool,
+}
+
+/// `None` when only the usage was asked for.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let command = args.next().ok_or("Missing command")?;
+    if matches!(command.as_str(), "help" | "-h" | "--help") {
+        return Ok(None);
+    }
+    let mut directory = None;
+    let mut version = None;
+    let mut pin = false;
+    let mut repair = false;
+    let mut interactive = io::stdin().is_terminal();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--version" if command == "install" => {
+                version = Some(args.next().ok_or("--version needs a version")?);
+            }
+            "--pin" if command == "install" => pin = true,
+            "--repair" if command == "verify" => repair = true,
+            "--non-interactive" => interactive = false,
+            _ if arg.starts_with("--") => {
+                return Err(format!("Unknown option {} for {}", arg, command))
+            }
+            _ if directory.is_none() => directory = Some(arg),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    if pin && version.is_none() {
+        return Err("--pin needs --version".to_string());
+    }
+    let command = match command.as_str() {
+        "check" => Command::Check,
+        "install" => Command::Install { version, pin },
+        "update" => Command::Update,
+        "verify" => Command::Verify { repair },
+        "status" => Command::Status,
+        _ => return Err(format!("Unknown command {}", command)),
+    };
+    Ok(Some(Args {
+        command,
+        directory: directory.ok_or("Missing install directory")?,
+        interactive,
+    }))
+}
+
+/// Draws one progress bar per step on stderr. When stderr isn't a terminal each step is
+/// printed once instead.
+struct TerminalProgress {
+    draw_bars: bool,
+    step: Mutex<Option<String>>,
+}
+
+impl TerminalProgress {
+    const WIDTH: usize = 30;
+
+    fn new() -> Self {
+        TerminalProgress {
+            draw_bars: io::stderr().is_terminal(),
+            step: Mutex::new(None),
+        }
+    }
+
+    /// Ends the line of a bar left unfinished, e.g. by a cancelled download.
+    fn finish(&self) {
+        if self.draw_bars && self.step.lock().unwrap().take().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn progress(&self, progress: &Progress) {
+        let mut step = self.step.lock().unwrap();
+        let new_step = step.as_deref() != Some(progress.name.as_str());
+        let mut stderr = io::stderr().lock();
+        if !self.draw_bars {
+            if new_step {
+                let _ = writeln!(stderr, "{} ({})", progress.name, progress.total_size);
+                *step = Some(progress.name.clone());
+            }
+            return;
+        }
+        if new_step && step.is_some() {
+            let _ = writeln!(stderr);
+        }
+        let percentage = progress.progress.trim_end_matches('%').parse::<f64>().ok();
+        let filled = percentage
+            .map(|percentage| ((percentage / 100.0) * Self::WIDTH as f64).round() as usize)
+            .unwrap_or(0)
+            .min(Self::WIDTH);
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K{} [{}{}] {:>7} {} / {} {}",
+            progress.name,
+            "#".repeat(filled),
+            "-".repeat(Self::WIDTH - filled),
+            progress.progress,
+            progress.current_size,
+            progress.total_size,
+            progress.speed
+        );
+        // Finished steps get their own line so the log doesn't run into the bar
+        if percentage.is_none_or(|percentage| percentage >= 100.0) {
+            let _ = writeln!(stderr);
+            *step = None;
+        } else {
+            let _ = stderr.flush();
+            *step = Some(progress.name.clone());
+        }
+    }
+}
+
+/// Asks on the terminal, falling back to [`HeadlessPrompter`] when nobody can answer.
+struct TerminalPrompter {
+    interactive: bool,
+    headless: HeadlessPrompter,
+}
+
+impl Prompter for TerminalPrompter {
+    fn notify(&self, title: &str, message: &str) {
+        self.headless.notify(title, message);
+    }
+
+    fn confirm(&self, confirmation: Confirmation) -> BoxFuture<'_, bool> {
+        if !self.interactive {
+            return self.headless.confirm(confirmation);
+        }
+        let default = self.headless.answer(confirmation.key);
+        Box::pin(async move {
+            let mut control = DOWNLOAD_CONTROL.subscribe();
+            let answer = tokio::task::spawn_blocking(move || {
+                let hint = if default { "Y/n" } else { "y/N" };
+                eprintln!("\n{}\n{}", confirmation.title, confirmation.message);
+                loop {
+                    eprint!("{} / {} [{}] ", confirmation.ok, confirmation.cancel, hint);
+                    let _ = io::stderr().flush();
+                    let mut line = String::new();
+                    if io::stdin().lock().read_line(&mut line).unwrap_or(0) == 0 {
+                        return default;
+                    }
+                    match line.trim().to_lowercase().as_str() {
+                        "" => return default,
+                        "y" | "yes" => return true,
+                        "n" | "no" => return false,
+                        _ => eprintln!("Please answer y or n"),
+                    }
+                }
+            });
+            // Ctrl+C declines; the read is abandoned, the runtime doesn't wait for it
+            let cancelled =
+                control.wait_for(|control| matches!(control, DownloadControl::Cancelled { .. }));
+            tokio::select! {
+                answer = answer => answer.unwrap_or(default),
+                _ = cancelled => false,
+            }
+        })
+    }
+}
+
+fn print_plan(plan: &UpdatePlan) {
+    let (title, message) = plan.summary();
+    println!("{}: {}", title, message);
+    if plan.status != UpdateStatus::Available {
+        return;
+    }
+    for archive in &plan.archives {
+        println!(
+            "  {} {}{}",
+            archive.version,
+            archive.path,
+            archive
+                .size
+                .map(|size| format!(" ({})", format_size(size)))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(total_size) = plan.total_size {
+        println!("Total download: {}", format_size(total_size));
+    }
+}
+
+fn print_report(report: &VerifyReport) {
+    println!("Checked {} file(s) of {}", report.checked, report.version);
+    for (label, paths) in [
+        ("Missing", &report.missing),
+        ("Modified", &report.modified),
+        ("Not installed by the mod", &report.extra),
+    ] {
+        for path in paths {
+            println!("  {}: {}", label, path);
+        }
+    }
+}
+
+fn status(directory: &str) -> Result<u8, Box<dyn std::error::Error>> {
+    let install_dir = Path::new(directory);
+    let settings = load_settings(install_dir);
+    let code = match load_receipt(install_dir)? {
+        Some(receipt) => {
+            println!(
+                "Installed: {} ({})",
+                receipt.version,
+                receipt.channel.name()
+            );
+            println!("Tracked files: {}", receipt.files.len());
+            EXIT_OK
+        }
+        None => {
+            println!("Not installed");
+            EXIT_NOT_INSTALLED
+        }
+    };
+    println!("Channel: {}", settings.channel.name());
+    if let Some(pinned_version) = &settings.pinned_version {
+        println!("Pinned to: {}", pinned_version);
+    }
+    println!("Rollback snapshots: {}", list_snapshots(install_dir)?.len());
+    Ok(code)
+}
+
+async fn run_command(args: Args) -> Result<u8, Box<dyn std::error::Error>> {
+    let directory = args.directory.as_str();
+    if !matches!(args.command, Command::Status) {
+        recover_interrupted_update(Path::new(directory))?;
+    }
+    let finished = |done: bool| if done { EXIT_OK } else { EXIT_CANCELLED };
+    match args.command {
+        Command::Check => {
+            let plan = check_updates(directory).await?;
+            print_plan(&plan);
+            Ok(match plan.status {
+                UpdateStatus::NotInstalled => EXIT_NOT_INSTALLED,
+                UpdateStatus::UpToDate | UpdateStatus::AheadOfManifest => EXIT_OK,
+                UpdateStatus::Available
+                | UpdateStatus::Unsupported
+                | UpdateStatus::ChannelSwitch => EXIT_UPDATE_AVAILABLE,
+            })
+        }
+        Command::Install {
+            version: Some(version),
+            pin,
+        } => Ok(finished(install_version(&version, pin, directory).await?)),
+        Command::Install { version: None, .. } => {
+            let plan = check_updates(directory).await?;
+            if plan.status != UpdateStatus::NotInstalled {
+                let (_, message) = plan.summary();
+                println!("{}\nUse update to change an existing install.", message);
+                return Ok(EXIT_OK);
+            }
+            Ok(finished(install_full_mod(directory, None).await?))
+        }
+        Command::Update => {
+            let plan = check_updates(directory).await?;
+            if plan.status == UpdateStatus::NotInstalled {
+                let (_, message) = plan.summary();
+                eprintln!("{}\nUse install to download the full mod.", message);
+                return Ok(EXIT_NOT_INSTALLED);
+            }
+            print_plan(&plan);
+            Ok(finished(apply_update_plan(plan, directory).await?))
+        }
+        Command::Verify { repair } => {
+            if load_receipt(Path::new(directory))?.is_none() {
+                eprintln!("No Convergence install was found in this directory");
+                return Ok(EXIT_NOT_INSTALLED);
+            }
+            let report = if repair {
+                repair_installation(Path::new(directory)).await?
+            } else {
+                verify_installation(Path::new(directory)).await?
+            };
+            print_report(&report);
+            Ok(if report.is_ok() {
+                EXIT_OK
+            } else {
+                EXIT_VERIFY_FAILED
+            })
+        }
+        Command::Status => status(directory),
+    }
+}
+
+/// Entry point of the CLI binary.
+pub fn run_cli() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_OK);
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let progress = Arc::new(TerminalProgress::new());
+    set_core(Core {
+        sink: progress.clone(),
+        prompter: Arc::new(TerminalPrompter {
+            interactive: args.interactive,
+            headless: HeadlessPrompter::from_env(),
+        }),
+        ..Core::default()
+    });
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start the async runtime");
+    let code = runtime.block_on(async {
+        // Ctrl+C keeps the partial download so the next run resumes it. Steps that can't
+        // be cancelled half way finish first, unless Ctrl+C is pressed again.
+        tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            eprintln!("\nCancelling, press Ctrl+C again to quit immediately");
+            set_download_control(DownloadControl::Cancelled {
+                delete_partial: false,
+            });
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("\nQuitting");
+                std::process::exit(EXIT_CANCELLED.into());
+            }
+        });
+        set_download_control(DownloadControl::Running);
+        run_command(args).await
+    });
+    // A prompt cancelled by Ctrl+C may still be blocked reading stdin
+    runtime.shutdown_background();
+    progress.finish();
+    match code {
+        Ok(code) => ExitCode::from(code),
+        Err(err) if err.is::<DownloadCancelled>() => {
+            eprintln!("Download cancelled");
+            ExitCode::from(EXIT_CANCELLED)
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
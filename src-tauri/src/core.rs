@@ -0,0 +1,72 @@
+use crate::events::{ProgressSink, SilentSink};
+use crate::prompt::{HeadlessPrompter, Prompter};
+use dotenvy_macro::dotenv;
+use once_cell::sync::Lazy;
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+/// Where the manifest and archives come from and which key signs them.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub updates_url: String,
+    /// Base URL of the Dropbox API, or of a server that answers `files/get_temporary_link`
+    /// the same way.
+    pub api_url: String,
+    pub dropbox_token: String,
+    /// Base64 minisign public key the manifest and archives are signed with.
+    pub public_key: String,
+}
+
+/// The values baked in at build time.
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            updates_url: dotenv!("UPDATES_URL").to_string(),
+            api_url: "https://api.dropboxapi.com/2".to_string(),
+            dropbox_token: dotenv!("DROPBOX_TOKEN").to_string(),
+            public_key: dotenv!("UPDATES_PUBLIC_KEY").to_string(),
+        }
+    }
+}
+
+/// Everything the update logic needs from whoever runs it: the app window, the CLI or a
+/// test harness.
+pub struct Core {
+    pub endpoints: Endpoints,
+    /// Where archives are downloaded to before they are extracted.
+    pub download_dir: PathBuf,
+    pub sink: Arc<dyn ProgressSink>,
+    pub prompter: Arc<dyn Prompter>,
+}
+
+/// Runs without any output besides the log, answering prompts from `ER_DOWNLOADER_ANSWERS`.
+impl Default for Core {
+    fn default() -> Self {
+        Core {
+            endpoints: Endpoints::default(),
+            download_dir: PathBuf::from("Download"),
+            sink: Arc::new(SilentSink),
+            prompter: Arc::new(HeadlessPrompter::from_env()),
+        }
+    }
+}
+
+impl Core {
+    /// Where the archive at the Dropbox path `archive_path` is downloaded to.
+    pub fn download_path(&self, archive_path: &str) -> PathBuf {
+        self.download_dir.join(archive_path.trim_start_matches('/'))
+    }
+}
+
+static CORE: Lazy<RwLock<Arc<Core>>> = Lazy::new(|| RwLock::new(Arc::new(Core::default())));
+
+/// Replaces the core used by every update operation started afterwards.
+pub fn set_core(core: Core) {
+    *CORE.write().unwrap() = Arc::new(core);
+}
+
+pub fn core() -> Arc<Core> {
+    CORE.read().unwrap().clone()
+}
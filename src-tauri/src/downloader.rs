@@ -1,124 +1,414 @@
-use crate::helpers::{format_size, format_speed, DropboxResponse, GLOBAL_APP_HANDLE};
-use curl::easy::{Easy, WriteError};
-use dotenvy_macro::dotenv;
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use crate::cleanup::offer_orphan_cleanup;
+use crate::core::core;
+use crate::events::{report_progress, Progress};
+use crate::helpers::{
+    dropbox_content_hash, fetch_manifest, format_size, format_speed, sha256_file, DropboxResponse,
+    Metadata, UpdateArchive, UpdateInfo, UpdatePlan, UpdateStatus,
+};
+use crate::install::{run_blocking, PendingArchive, StagedUpdate};
+use crate::migrate::migrate_install;
+use crate::planner::{plan_route, UpdateRoute};
+use crate::prompt::{confirm, notify, PromptKey};
+use crate::receipt::load_receipt;
+use crate::settings::{load_settings, save_settings, Channel, ConflictPolicy};
+use crate::signature::verify_file_signature;
+use crate::version::ModVersion;
+use once_cell::sync::Lazy;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT_RANGES, AUTHORIZATION, CONTENT_LENGTH, RANGE},
+    StatusCode,
+};
 use serde_json::json;
 use std::{
-    fs::{self, File, OpenOptions},
-    io::Write,
+    fmt, fs,
+    io::SeekFrom,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use tauri::Emitter;
-use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
-
-#[derive(serde::Serialize, Clone)]
-struct Progress {
-    name: String,
-    total_size: String,
-    current_size: String,
-    speed: String,
-    progress: String,
+use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::watch,
+    task::JoinSet,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadControl {
+    Running,
+    Paused,
+    Cancelled { delete_partial: bool },
+}
+
+pub static DOWNLOAD_CONTROL: Lazy<watch::Sender<DownloadControl>> =
+    Lazy::new(|| watch::channel(DownloadControl::Running).0);
+
+#[derive(Debug)]
+pub struct DownloadCancelled;
+
+impl fmt::Display for DownloadCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Download cancelled")
+    }
+}
+
+impl std::error::Error for DownloadCancelled {}
+
+pub fn set_download_control(control: DownloadControl) {
+    DOWNLOAD_CONTROL.send_replace(control);
+}
+
+fn check_cancelled() -> Result<(), Box<dyn std::error::Error>> {
+    match *DOWNLOAD_CONTROL.borrow() {
+        DownloadControl::Cancelled { .. } => Err(Box::new(DownloadCancelled)),
+        _ => Ok(()),
+    }
+}
+
+const MAX_SEGMENTS: u64 = 8;
+const MIN_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone)]
+struct Segment {
+    start: u64,
+    end: u64,
+    downloaded: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SegmentState {
+    total_size: u64,
+    segments: Vec<Segment>,
+}
+
+fn split_segments(total_size: u64, accepts_ranges: bool) -> Vec<Segment> {
+    let count = if accepts_ranges {
+        (total_size / MIN_SEGMENT_SIZE).clamp(1, MAX_SEGMENTS)
+    } else {
+        1
+    };
+    let segment_size = total_size.div_ceil(count);
+    (0..count)
+        .map(|i| {
+            let start = i * segment_size;
+            Segment {
+                start,
+                end: (start + segment_size).min(total_size) - 1,
+                downloaded: 0,
+            }
+        })
+        .collect()
+}
+
+async fn load_segment_state(path: &Path) -> Option<SegmentState> {
+    let data = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+async fn save_segment_state(path: &Path, state: &SegmentState) -> std::io::Result<()> {
+    tokio::fs::write(path, serde_json::to_vec(state)?).await
+}
+
+async fn download_segment(
+    client: reqwest::Client,
+    url: String,
+    output: PathBuf,
+    segment: Segment,
+    downloaded: Arc<AtomicU64>,
+    ranged: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let length = segment.end - segment.start + 1;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&output)
+        .await?;
+    // Without ranges the server always sends the file from byte 0, so resuming starts over
+    if !ranged && downloaded.load(Ordering::SeqCst) > 0 {
+        println!("Server doesn't support resuming, restarting the download");
+        downloaded.store(0, Ordering::SeqCst);
+        file.set_len(0).await?;
+    }
+    let offset = segment.start + downloaded.load(Ordering::SeqCst);
+    if offset > segment.end {
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut request = client.get(&url);
+    if ranged {
+        request = request.header(RANGE, format!("bytes={}-{}", offset, segment.end));
+    }
+    let mut response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP error: {}", response.status()).into());
+    }
+    if ranged && response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err("Server did not honour the range request".into());
+    }
+
+    let mut control = DOWNLOAD_CONTROL.subscribe();
+    loop {
+        let mut interrupted = false;
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk?,
+            _ = control.wait_for(|state| *state != DownloadControl::Running) => {
+                interrupted = true;
+                None
+            }
+        };
+        if interrupted {
+            // Paused or cancelled: keep what we have, the caller decides what happens next
+            file.flush().await?;
+            return Ok(());
+        }
+        let Some(chunk) = chunk else {
+            break;
+        };
+        // Never write past the end of the segment
+        if downloaded.load(Ordering::SeqCst) + chunk.len() as u64 > length {
+            return Err("Server sent more data than requested".into());
+        }
+        file.write_all(&chunk).await?;
+        downloaded.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+    }
+    file.flush().await?;
+
+    if downloaded.load(Ordering::SeqCst) < length {
+        return Err(format!("Segment {}-{} ended early", segment.start, segment.end).into());
+    }
+    Ok(())
+}
+
+async fn verify_download(
+    output: &Path,
+    metadata: &Metadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Verifying content hash of {}", output.display());
+    report_progress(Progress {
+        name: "Verifying download...".to_string(),
+        total_size: format_size(metadata.size as u64),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "100%".to_string(),
+    });
+    let content_hash = dropbox_content_hash(output).await?;
+    if content_hash != metadata.content_hash {
+        println!(
+            "Content hash mismatch for {}: expected {}, got {}. Removing file.",
+            metadata.name, metadata.content_hash, content_hash
+        );
+        tokio::fs::remove_file(output).await?;
+        return Err(format!("Content hash mismatch for {}", metadata.name).into());
+    }
+    Ok(())
 }
 
 pub async fn download_file(
     link: &str,
-    download_to: &str,
+    download_to: &Path,
+    metadata: &Metadata,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let url = link;
-    let output = PathBuf::from(download_to);
-    fs::create_dir_all(output.parent().unwrap())?;
+    let output = download_to.to_path_buf();
+    let mut state_path = download_to.as_os_str().to_owned();
+    state_path.push(".parts");
+    let state_path = PathBuf::from(state_path);
+    tokio::fs::create_dir_all(output.parent().unwrap()).await?;
 
-    // First get the total file size
-    let mut easy = Easy::new();
-    easy.url(url)?;
-    easy.nobody(true)?;
-    easy.perform()?;
-    let total_size = easy.content_length_download()? as u64;
+    // First get the total file size and whether the server supports range requests
+    let client = reqwest::Client::new();
+    let probe = client.head(url).send().await?;
+    if !probe.status().is_success() {
+        return Err(format!("HTTP error: {}", probe.status()).into());
+    }
+    let total_size = probe
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    let accepts_ranges = probe
+        .headers()
+        .get(ACCEPT_RANGES)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("bytes"));
 
     println!("Total file size: {}", format_size(total_size));
     if total_size == 0 {
         return Err("Could not determine file size".into());
     }
-
-    // Open the file in append mode to support resuming
-    let mut file = File::options().create(true).append(true).open(&output)?;
-    let current_size = file.metadata()?.len();
-
-    if current_size >= total_size {
-        println!(
-            "File already exists and is complete ({}). Skipping download.",
-            format_size(current_size)
-        );
-        return Ok(());
+    if total_size != metadata.size as u64 {
+        return Err(format!(
+            "Size mismatch for {}: expected {}, server reports {}",
+            metadata.name,
+            format_size(metadata.size as u64),
+            format_size(total_size)
+        )
+        .into());
     }
 
-    // Curl handle for actual download
-    let mut easy = Easy::new();
-    easy.url(url)?;
-    easy.follow_location(true)?;
-    easy.resume_from(current_size)?;
+    // Pick up the segments of a previous attempt, or preallocate a fresh file
+    let state = match load_segment_state(&state_path).await {
+        Some(state) if accepts_ranges && state.total_size == total_size && output.exists() => state,
+        _ => {
+            if !state_path.exists()
+                && output.exists()
+                && tokio::fs::metadata(&output).await?.len() == total_size
+            {
+                println!(
+                    "File already exists and is complete ({}). Skipping download.",
+                    format_size(total_size)
+                );
+                return verify_download(&output, metadata).await;
+            }
+            let file = tokio::fs::File::create(&output).await?;
+            file.set_len(total_size).await?;
+            let state = SegmentState {
+                total_size,
+                segments: split_segments(total_size, accepts_ranges),
+            };
+            save_segment_state(&state_path, &state).await?;
+            state
+        }
+    };
 
-    let start_size = current_size;
-    easy.progress(true)?;
+    let counters: Vec<Arc<AtomicU64>> = state
+        .segments
+        .iter()
+        .map(|segment| Arc::new(AtomicU64::new(segment.downloaded)))
+        .collect();
+    let start_size: u64 = state
+        .segments
+        .iter()
+        .map(|segment| segment.downloaded)
+        .sum();
+    println!(
+        "Downloading {} over {} connection(s), {} already present",
+        format_size(total_size),
+        state.segments.len(),
+        format_size(start_size)
+    );
 
     let start_time = std::time::Instant::now();
     let file_name = download_to
-        .rsplit_once("/")
-        .map(|(_, name)| name.to_string())
-        .unwrap_or_else(|| download_to.to_string());
-    easy.progress_function(move |total, now, _, _| {
-        if total > 0.0 {
-            let current = start_size + now as u64;
-            let percentage = (current as f64 / total_size as f64) * 100.0;
-
-            let elapsed = start_time.elapsed().as_secs_f64();
-            let speed = if elapsed > 0.0 { now / elapsed } else { 0.0 };
-            if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                app_handle
-                    .emit(
-                        "download_progress",
-                        Progress {
-                            name: format!("{}", file_name.replace(".zip", "")),
-                            total_size: format_size(total_size),
-                            current_size: format_size(current),
-                            speed: format_speed(speed),
-                            progress: format!("{:.2}%", percentage),
-                        },
-                    )
-                    .unwrap();
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| download_to.display().to_string());
+
+    let mut failure: Option<String> = None;
+    loop {
+        let mut tasks = JoinSet::new();
+        for (segment, counter) in state.segments.iter().zip(&counters) {
+            tasks.spawn(download_segment(
+                client.clone(),
+                url.to_string(),
+                output.clone(),
+                segment.clone(),
+                counter.clone(),
+                accepts_ranges,
+            ));
+        }
+
+        // Report progress and persist segment offsets while the workers run
+        let mut ticker = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            tokio::select! {
+                joined = tasks.join_next() => match joined {
+                    None => break,
+                    Some(Ok(Ok(()))) => {}
+                    Some(Ok(Err(e))) => {
+                        println!("Segment failed: {}", e);
+                        failure.get_or_insert(e.to_string());
+                    }
+                    Some(Err(e)) => {
+                        println!("Segment worker failed: {}", e);
+                        failure.get_or_insert(e.to_string());
+                    }
+                },
+                _ = ticker.tick() => {
+                    let current: u64 = counters.iter().map(|c| c.load(Ordering::SeqCst)).sum();
+                    let percentage = (current as f64 / total_size as f64) * 100.0;
+
+                    let elapsed = start_time.elapsed().as_secs_f64();
+                    let speed = if elapsed > 0.0 {
+                        (current - start_size) as f64 / elapsed
+                    } else {
+                        0.0
+                    };
+                    report_progress(Progress {
+                                    name: file_name.replace(".zip", ""),
+                                    total_size: format_size(total_size),
+                                    current_size: format_size(current),
+                                    speed: format_speed(speed),
+                                    progress: format!("{:.2}%", percentage),
+                                });
+                    let _ = save_segment_state(&state_path, &snapshot_state(&state, &counters)).await;
+                }
             }
         }
-        true
-    })?;
 
-    // Write the downloaded data to the file
-    {
-        let mut transfer = easy.transfer();
-        transfer.write_function(|data| {
-            file.write_all(data).map_err(|_| WriteError::Pause)?;
-            Ok(data.len())
-        })?;
-        transfer.perform()?;
+        if failure.is_some() {
+            break;
+        }
+        let control = *DOWNLOAD_CONTROL.borrow();
+        match control {
+            DownloadControl::Running => {
+                let current: u64 = counters.iter().map(|c| c.load(Ordering::SeqCst)).sum();
+                if current == total_size {
+                    break;
+                }
+            }
+            DownloadControl::Paused => {
+                save_segment_state(&state_path, &snapshot_state(&state, &counters)).await?;
+                println!("Download paused");
+                let current: u64 = counters.iter().map(|c| c.load(Ordering::SeqCst)).sum();
+                report_progress(Progress {
+                    name: format!("Paused: {}", file_name.replace(".zip", "")),
+                    total_size: format_size(total_size),
+                    current_size: format_size(current),
+                    speed: "N/A".to_string(),
+                    progress: format!("{:.2}%", (current as f64 / total_size as f64) * 100.0),
+                });
+                let mut control = DOWNLOAD_CONTROL.subscribe();
+                let _ = control
+                    .wait_for(|state| *state != DownloadControl::Paused)
+                    .await;
+                println!("Download resumed");
+            }
+            DownloadControl::Cancelled { delete_partial } => {
+                if delete_partial {
+                    println!("Download cancelled, removing partial file");
+                    let _ = tokio::fs::remove_file(&output).await;
+                    let _ = tokio::fs::remove_file(&state_path).await;
+                } else {
+                    println!("Download cancelled, keeping partial file");
+                    save_segment_state(&state_path, &snapshot_state(&state, &counters)).await?;
+                }
+                return Err(Box::new(DownloadCancelled));
+            }
+        }
+    }
+
+    save_segment_state(&state_path, &snapshot_state(&state, &counters)).await?;
+    if let Some(e) = failure {
+        return Err(e.into());
     }
 
     // Verify download completion
-    let final_size = file.metadata()?.len();
+    let final_size: u64 = counters.iter().map(|c| c.load(Ordering::SeqCst)).sum();
     if final_size == total_size {
+        tokio::fs::remove_file(&state_path).await?;
+        verify_download(&output, metadata).await?;
         println!("Download completed successfully");
-        if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-            app_handle
-                .emit(
-                    "download_progress",
-                    Progress {
-                        name: format!("Download completed successfully"),
-                        total_size: format_size(total_size),
-                        current_size: format_size(final_size),
-                        speed: "N/A".to_string(),
-                        progress: "100%".to_string(),
-                    },
-                )
-                .unwrap();
-        }
+        report_progress(Progress {
+            name: "Download completed successfully".to_string(),
+            total_size: format_size(total_size),
+            current_size: format_size(final_size),
+            speed: "N/A".to_string(),
+            progress: "100%".to_string(),
+        });
 
         Ok(())
     } else {
@@ -131,11 +421,29 @@ pub async fn download_file(
     }
 }
 
-pub async fn generate_download_link(file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
+fn snapshot_state(state: &SegmentState, counters: &[Arc<AtomicU64>]) -> SegmentState {
+    SegmentState {
+        total_size: state.total_size,
+        segments: state
+            .segments
+            .iter()
+            .zip(counters)
+            .map(|(segment, counter)| Segment {
+                downloaded: counter.load(Ordering::SeqCst),
+                ..segment.clone()
+            })
+            .collect(),
+    }
+}
+
+pub async fn generate_download_link(
+    file_path: &str,
+) -> Result<DropboxResponse, Box<dyn std::error::Error>> {
     println!("Generating download link...");
-    let dropbox_token = dotenv!("DROPBOX_TOKEN");
+    let endpoints = core().endpoints.clone();
+    let dropbox_token = endpoints.dropbox_token;
     // let file_path = "/ConvergenceER.zip";
-    let url = "https://api.dropboxapi.com/2/files/get_temporary_link";
+    let url = format!("{}/files/get_temporary_link", endpoints.api_url);
     let client = reqwest::Client::new();
     let mut headers = HeaderMap::new();
     let auth_token: String = format!("Bearer {}", dropbox_token);
@@ -150,199 +458,514 @@ pub async fn generate_download_link(file_path: &str) -> Result<String, Box<dyn s
     let status = req.status();
     if status.is_success() {
         let response: DropboxResponse = req.json().await?;
-        Ok(response.link)
+        Ok(response)
     } else {
         let error_message = req.text().await?;
         Err(error_message.into())
     }
 }
 
-pub async fn download_updates(
-    updates: Vec<String>,
-    extract_path: &str,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let mut latest_version = String::from("v0.0.0");
-    if updates.is_empty() {
-        if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-            let answer = app_handle
-                .dialog()
-                .message("Directory doesn't contain a version.txt\nDo you want to download The full mod?")
-                .title("Tauri is Awesome")
-                .buttons(MessageDialogButtons::OkCancel)
-                .blocking_show();
-            if answer {
-                print!("Downloading file...");
-                let download_link = generate_download_link("/ConvergenceER.zip")
-                    .await
-                    .map_err(|e| e.to_string())?;
-                println!("Download link: {}", download_link);
-                let mut retries = 0;
-                let max_retries = 3;
-
-                loop {
-                    match download_file(&download_link, "Download/Convergence.zip").await {
-                        Ok(_) => {
-                            let file = File::open("Download/Convergence.zip")
-                                .map_err(|e| e.to_string())?;
-                            let mut archive =
-                                zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
-                            println!("Extracting file...");
-                            println!("Extracting to: {}", extract_path);
-                            if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                                app_handle
-                                    .emit(
-                                        "download_progress",
-                                        Progress {
-                                            name: format!("Extracting to: {}", extract_path),
-                                            total_size: "N/A".to_string(),
-                                            current_size: "N/A".to_string(),
-                                            speed: "N/A".to_string(),
-                                            progress: "100%".to_string(),
-                                        },
-                                    )
-                                    .unwrap();
-                            }
-                            archive.extract(extract_path).map_err(|e| e.to_string())?;
-                            println!("File extracted successfully!");
-                            fs::remove_file("Download/Convergence.zip")
-                                .expect("Failed to remove file");
-                            if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                                app_handle
-                                    .dialog()
-                                    .message("File extracted successfully!")
-                                    .blocking_show();
-                            }
-
-                            return Ok(true);
-                        }
-                        Err(_) => {
-                            retries += 1;
-                            if retries >= max_retries {
-                                return Ok(false);
-                            }
-                            println!("Download failed, retrying ({}/{})", retries, max_retries);
-                        }
-                    }
-                }
-            }
+async fn verify_archive(
+    output: &Path,
+    update: &UpdateArchive,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(size) = update.size {
+        let actual = tokio::fs::metadata(output).await?.len();
+        if actual != size {
+            tokio::fs::remove_file(output).await?;
+            return Err(format!(
+                "Integrity check failed for {}: expected {} bytes, got {} bytes",
+                update.path, size, actual
+            )
+            .into());
         }
-        return Ok(false);
     }
-    println!("Downloading updates...");
-    for update in updates {
-        let (_, update_name) = update.rsplit_once('/').unwrap();
-        println!("Downloading update: {}", update_name);
-        if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-            app_handle
-                .emit(
-                    "download_progress",
-                    Progress {
-                        name: format!("{}", update_name.replace(".zip", "")),
-                        total_size: "N/A".to_string(),
-                        current_size: "N/A".to_string(),
-                        speed: "N/A".to_string(),
-                        progress: "N/A".to_string(),
-                    },
-                )
-                .unwrap();
+    if let Some(sha256) = &update.sha256 {
+        let actual = sha256_file(output).await?;
+        if !actual.eq_ignore_ascii_case(sha256) {
+            tokio::fs::remove_file(output).await?;
+            return Err(format!(
+                "Integrity check failed for {}: expected sha256 {}, got {}",
+                update.path, sha256, actual
+            )
+            .into());
         }
-        let download_link = generate_download_link(&update)
-            .await
-            .map_err(|e| e.to_string())?;
-        let output_path = format!("Download/{}", update);
-        let mut retries = 0;
-        let max_retries = 3;
+    }
+    if let Some(signature) = &update.signature {
+        if let Err(e) = verify_file_signature(output, signature).await {
+            tokio::fs::remove_file(output).await?;
+            return Err(format!("Integrity check failed for {}: {}", update.path, e).into());
+        }
+    }
+    Ok(())
+}
 
-        loop {
-            match download_file(&download_link, &output_path).await {
-                Ok(_) => {
-                    println!("Download completed successfully: {}", update_name);
-                    println!("Extracting file...");
-                    if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                        app_handle
-                            .emit(
-                                "download_progress",
-                                Progress {
-                                    name: format!("Extracting..."),
-                                    total_size: "N/A".to_string(),
-                                    current_size: "N/A".to_string(),
-                                    speed: "N/A".to_string(),
-                                    progress: "100%".to_string(),
-                                },
-                            )
-                            .unwrap();
-                    }
-                    let file_path = format!("Download/{}", update);
-                    let file = File::open(file_path).map_err(|e| e.to_string())?;
-                    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
-                    archive.extract(extract_path).map_err(|e| e.to_string())?;
-                    println!("File extracted successfully!");
-                    fs::remove_file(output_path).expect("Failed to remove file");
-                    if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                        app_handle
-                            .emit(
-                                "download_progress",
-                                Progress {
-                                    name: format!("Extraction completed"),
-                                    total_size: "N/A".to_string(),
-                                    current_size: "N/A".to_string(),
-                                    speed: "N/A".to_string(),
-                                    progress: "100%".to_string(),
-                                },
-                            )
-                            .unwrap();
-                    }
-                    break;
-                }
-                Err(_) => {
-                    retries += 1;
-                    if retries >= max_retries {
-                        return Ok(false);
-                    }
-                    println!("Download failed, retrying ({}/{})", retries, max_retries);
+/// Downloads an archive from Dropbox to `output_path`, retrying failed attempts.
+pub async fn download_archive(
+    archive_path: &str,
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let download = generate_download_link(archive_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut retries = 0;
+    let max_retries = 3;
+    loop {
+        match download_file(&download.link, output_path, &download.metadata).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.is::<DownloadCancelled>() => return Err(e),
+            Err(e) => {
+                retries += 1;
+                if retries >= max_retries {
+                    return Err(format!(
+                        "Failed to download {} after {} attempts: {}",
+                        archive_path, max_retries, e
+                    )
+                    .into());
                 }
+                println!("Download failed, retrying ({}/{})", retries, max_retries);
             }
         }
-
-        latest_version = update.replace("/updates/", "").replace(".zip", "");
     }
+}
+
+pub async fn install_full_mod(
+    extract_path: &str,
+    version: Option<&ModVersion>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let (output_path, archive) = download_full_archive(extract_path, version).await?;
+    install_full_archive(extract_path, version, &output_path, &archive).await
+}
 
-    let file_path = Path::new(extract_path).join("version.txt");
+/// Downloads and verifies the full archive of `version`, or of the channel's latest
+/// release, without touching the install.
+pub async fn download_full_archive(
+    extract_path: &str,
+    version: Option<&ModVersion>,
+) -> Result<(PathBuf, UpdateArchive), Box<dyn std::error::Error>> {
+    print!("Downloading file...");
+    let channel = load_settings(Path::new(extract_path)).channel;
+    // Only the latest release is guaranteed a full archive; older ones come from the manifest
+    let archive = match version {
+        Some(version) => fetch_manifest()
+            .await?
+            .for_channel(channel)?
+            .full_archive(version, channel)
+            .ok_or_else(|| format!("No full archive is published for {}", version))?,
+        None => UpdateArchive::from_path(channel.full_archive()),
+    };
+    let output_path = core().download_dir.join("Convergence.zip");
+    download_archive(&archive.path, &output_path).await?;
+    verify_archive(&output_path, &archive).await?;
+    Ok((output_path, archive))
+}
 
-    if file_path.exists() {
-        fs::remove_file(&file_path).map_err(|e| e.to_string())?;
+/// Extracts a full archive fetched by [`download_full_archive`] over the install.
+pub async fn install_full_archive(
+    extract_path: &str,
+    version: Option<&ModVersion>,
+    output_path: &Path,
+    archive: &UpdateArchive,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    println!("Extracting file...");
+    println!("Extracting to: {}", extract_path);
+    report_progress(Progress {
+        name: format!("Extracting to: {}", extract_path),
+        total_size: "N/A".to_string(),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "100%".to_string(),
+    });
+    let version = version.map(|version| version.to_string());
+    let mut transaction = StagedUpdate::begin(Path::new(extract_path), version.as_deref())?;
+    transaction.mark_full_install();
+    let (staged_path, archive_name) = (output_path.to_path_buf(), archive.path.clone());
+    let staged = transaction
+        .run_step(move |transaction| transaction.stage_archive(&staged_path, &archive_name))
+        .await;
+    if let Err(e) = staged {
+        transaction.abort()?;
+        return Err(e);
+    }
+    if !confirm_conflicts(&mut transaction).await? {
+        transaction.abort()?;
+        fs::remove_file(output_path)?;
+        return Ok(false);
     }
+    run_blocking(move || transaction.commit()).await?;
+    println!("File extracted successfully!");
+    fs::remove_file(output_path).expect("Failed to remove file");
+    notify("Install finished", "File extracted successfully!");
+    Ok(true)
+}
 
-    let mut version_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&file_path)
-        .map_err(|e| e.to_string())?;
+/// Installs `target` through the cheapest route [`plan_route`] finds, which for a fresh
+/// install or a downgrade can be an older full archive plus the updates after it.
+async fn install_route(
+    update_info: &UpdateInfo,
+    current: Option<&ModVersion>,
+    target: &ModVersion,
+    channel: Channel,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let Some(route) = plan_route(update_info, current, target, channel) else {
+        return Err(format!("No full archive or update chain reaches {}", target).into());
+    };
+    match current {
+        Some(current) => println!(
+            "Installing {} over {}: {}",
+            target,
+            current,
+            route.describe()
+        ),
+        None => println!("Installing {}: {}", target, route.describe()),
+    }
+    download_updates(route, extract_path).await
+}
 
-    if let Err(e) = write!(version_file, "{}", latest_version) {
-        eprintln!("Couldn't write to file: {}", e);
+/// Offers to downgrade an install newer than `latest`, which is the pinned version when
+/// `pinned` is set.
+pub async fn resolve_ahead_of_manifest(
+    current: &ModVersion,
+    latest: &ModVersion,
+    pinned: bool,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let (title, message) = if pinned {
+        println!(
+            "Installed version {} is newer than the pinned {}",
+            current, latest
+        );
+        (
+            "Newer than pinned version",
+            format!(
+                "You are using {}, which is newer than the version you pinned, {}.\n\
+                 You can keep it, or go back to {}.",
+                current, latest, latest
+            ),
+        )
+    } else {
+        println!(
+            "Installed version {} is newer than the published latest {}",
+            current, latest
+        );
+        (
+            "Newer than latest release",
+            format!(
+                "You are using {}, which is newer than the latest published version {}.\n\
+                 You can keep it, or downgrade to {}.",
+                current, latest, latest
+            ),
+        )
+    };
+    let downgrade = confirm(
+        PromptKey::Downgrade,
+        title,
+        &message,
+        &format!("Downgrade to {}", latest),
+        &format!("Stay on {}", current),
+    )
+    .await;
+    if !downgrade {
+        println!("Staying on {}", current);
+        return Ok(true);
     }
-    println!("Version file updated: {}", latest_version);
 
-    println!("All updates downloaded and extracted successfully!");
-    if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-        app_handle
-            .dialog()
-            .message("All updates downloaded and extracted successfully!")
-            .blocking_show();
-        app_handle
-            .emit(
-                "download_progress",
-                Progress {
-                    name: "All updates downloaded".to_string(),
-                    total_size: "N/A".to_string(),
-                    current_size: "N/A".to_string(),
-                    speed: "N/A".to_string(),
-                    progress: "N/A".to_string(),
-                },
+    println!("Downgrading to {}", latest);
+    let channel = load_settings(Path::new(extract_path)).channel;
+    let update_info = fetch_manifest().await?.for_channel(channel)?;
+    install_route(&update_info, Some(current), latest, channel, extract_path).await
+}
+
+/// Installs exactly `version` through the cheapest route to it. With `pin`, later checks
+/// stop offering newer versions; without it, a pin on another version is removed so the
+/// install isn't treated as ahead of it.
+pub async fn install_version(
+    version: &str,
+    pin: bool,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let install_dir = Path::new(extract_path);
+    let mut settings = load_settings(install_dir);
+    let version: ModVersion = version.parse()?;
+    let update_info = fetch_manifest().await?.for_channel(settings.channel)?;
+    let Some(target) = update_info.versions().into_iter().find(|v| *v == version) else {
+        return Err(format!(
+            "{} is not a published {} version",
+            version,
+            settings.channel.name()
+        )
+        .into());
+    };
+    let current = match load_receipt(install_dir)? {
+        Some(receipt) => Some(receipt.installed_version()?),
+        None => None,
+    };
+
+    let installed = match &current {
+        Some(current) if *current == target => {
+            println!("{} is already installed", target);
+            true
+        }
+        current => {
+            let channel = settings.channel;
+            install_route(
+                &update_info,
+                current.as_ref(),
+                &target,
+                channel,
+                extract_path,
             )
-            .unwrap();
+            .await?
+        }
+    };
+
+    if installed && pin {
+        println!("Pinning {}", target);
+        settings.pinned_version = Some(target);
+        save_settings(install_dir, &settings)?;
+    } else if installed
+        && settings
+            .pinned_version
+            .as_ref()
+            .is_some_and(|pin| *pin != target)
+    {
+        println!(
+            "Removing the pin on {}",
+            settings.pinned_version.take().unwrap()
+        );
+        save_settings(install_dir, &settings)?;
     }
+    Ok(installed)
+}
+
+/// Lists the files the player edited that the staged update would touch, before
+/// anything is applied. Returns `false` if the player cancels the update.
+async fn confirm_conflicts(
+    transaction: &mut StagedUpdate,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let conflicts = transaction
+        .run_step(|transaction| transaction.conflicts())
+        .await?;
+    if conflicts.is_empty() {
+        return Ok(true);
+    }
+    core().sink.conflicts(&conflicts);
+    let mut message = String::from("You changed these files since they were installed:\n");
+    for conflict in &conflicts {
+        let action = match conflict.policy {
+            ConflictPolicy::Keep => "keep your copy",
+            ConflictPolicy::TakeNew => "replace with the new version",
+            ConflictPolicy::SaveAsNew => "keep your copy, save the new version as .new",
+        };
+        message.push_str(&format!("\n{}: {}", conflict.path, action));
+    }
+    message.push_str("\n\nCancel to change how these files are handled.");
+    Ok(confirm(
+        PromptKey::Conflicts,
+        "Modified files",
+        &message,
+        "Continue",
+        "Cancel update",
+    )
+    .await)
+}
+
+/// Replaces a build from a less stable channel with the selected channel's latest release.
+pub async fn switch_channel(
+    current: &ModVersion,
+    latest: &ModVersion,
+    from: Channel,
+    to: Channel,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    println!(
+        "Switching from {} {} to {} {}",
+        from.name(),
+        current,
+        to.name(),
+        latest
+    );
+    let switch = confirm(
+        PromptKey::SwitchChannel,
+        "Switch release channel",
+        &format!(
+            "You are using the {} build {}.\n\
+             Switching to {} downgrades to {} with a full reinstall.",
+            from.name(),
+            current,
+            to.name(),
+            latest
+        ),
+        &format!("Install {} {}", to.name(), latest),
+        &format!("Stay on {}", current),
+    )
+    .await;
+    if !switch {
+        println!("Staying on {}", current);
+        return Ok(true);
+    }
+
+    let installed = install_full_mod(extract_path, Some(latest)).await?;
+    if installed {
+        offer_orphan_cleanup(Path::new(extract_path)).await;
+    }
+    Ok(installed)
+}
 
+async fn stage_updates(
+    transaction: &mut StagedUpdate,
+    updates: Vec<(ModVersion, UpdateArchive)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Everything is downloaded and verified first, then extracted in a single pass
+    let mut downloaded = Vec::new();
+    for (_, update) in &updates {
+        check_cancelled()?;
+        let update_name = update.file_name();
+        println!("Downloading update: {}", update_name);
+        if let Some(description) = &update.description {
+            println!("{}", description);
+        }
+        report_progress(Progress {
+            name: format!("{}", update_name.replace(".zip", "")),
+            total_size: "N/A".to_string(),
+            current_size: "N/A".to_string(),
+            speed: "N/A".to_string(),
+            progress: "N/A".to_string(),
+        });
+        let output_path = core().download_path(&update.path);
+        download_archive(&update.path, &output_path).await?;
+        println!("Download completed successfully: {}", update_name);
+        verify_archive(&output_path, update).await?;
+        downloaded.push(PendingArchive {
+            path: output_path,
+            name: update.path.clone(),
+            operations: update.operations.clone(),
+        });
+    }
+
+    println!("Extracting {} update(s)...", downloaded.len());
+    report_progress(Progress {
+        name: format!("Extracting..."),
+        total_size: "N/A".to_string(),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "100%".to_string(),
+    });
+    transaction
+        .run_step(move |transaction| {
+            transaction.stage_archives(&downloaded)?;
+            for archive in &downloaded {
+                fs::remove_file(&archive.path)?;
+            }
+            Ok(())
+        })
+        .await?;
+    println!("File extracted successfully!");
+    report_progress(Progress {
+        name: format!("Extraction completed"),
+        total_size: "N/A".to_string(),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "100%".to_string(),
+    });
+
+    Ok(())
+}
+
+/// Carries out whatever the plan's status calls for: updating, switching channel,
+/// migrating, downgrading or a fresh install. Returns `false` if the player declined.
+pub async fn apply_update_plan(
+    plan: UpdatePlan,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let (Some(current), Some(latest)) = (&plan.current, &plan.latest) else {
+        return offer_full_install(extract_path).await;
+    };
+    match plan.status {
+        UpdateStatus::Available => match plan.route {
+            Some(route) => download_updates(route, extract_path).await,
+            None => Ok(false),
+        },
+        UpdateStatus::UpToDate => {
+            println!("Already on the latest version: {}", current);
+            Ok(true)
+        }
+        UpdateStatus::ChannelSwitch => {
+            let from = plan.installed_channel.unwrap_or_default();
+            switch_channel(current, latest, from, plan.channel, extract_path).await
+        }
+        UpdateStatus::AheadOfManifest => {
+            resolve_ahead_of_manifest(current, latest, plan.pinned, extract_path).await
+        }
+        UpdateStatus::Unsupported => {
+            let least = plan.least.as_ref().unwrap_or(latest);
+            migrate_install(current, least, latest, extract_path).await
+        }
+        UpdateStatus::NotInstalled => offer_full_install(extract_path).await,
+    }
+}
+
+pub async fn offer_full_install(extract_path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let answer = confirm(
+        PromptKey::FullInstall,
+        "Tauri is Awesome",
+        "No Convergence install was found in this directory\nDo you want to download The full mod?",
+        "Ok",
+        "Cancel",
+    )
+    .await;
+    if answer {
+        return install_full_mod(extract_path, None).await;
+    }
     Ok(false)
 }
+
+pub async fn download_updates(
+    route: UpdateRoute,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    println!("Downloading updates: {}", route.describe());
+    core().sink.route(&route);
+    let latest_version = route
+        .target()
+        .map(|version| version.to_string())
+        .unwrap_or_default();
+    let mut transaction = StagedUpdate::begin(Path::new(extract_path), Some(&latest_version))?;
+    if route.full.is_some() {
+        transaction.mark_full_install();
+    }
+    if let Err(e) = stage_updates(&mut transaction, route.into_archives()).await {
+        transaction.abort()?;
+        return Err(e);
+    }
+    if !confirm_conflicts(&mut transaction).await? {
+        transaction.abort()?;
+        return Ok(false);
+    }
+
+    report_progress(Progress {
+        name: "Applying update...".to_string(),
+        total_size: "N/A".to_string(),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "100%".to_string(),
+    });
+    run_blocking(move || transaction.commit()).await?;
+    offer_orphan_cleanup(Path::new(extract_path)).await;
+
+    println!("All updates downloaded and extracted successfully!");
+    notify(
+        "Update finished",
+        "All updates downloaded and extracted successfully!",
+    );
+    report_progress(Progress {
+        name: "All updates downloaded".to_string(),
+        total_size: "N/A".to_string(),
+        current_size: "N/A".to_string(),
+        speed: "N/A".to_string(),
+        progress: "N/A".to_string(),
+    });
+
+    Ok(true)
+}
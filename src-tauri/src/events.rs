@@ -0,0 +1,36 @@
+use crate::core::core;
+pub use crate::install::Conflict;
+pub use crate::planner::UpdateRoute;
+use serde::Serialize;
+
+/// A progress update, already formatted for display.
+#[derive(Serialize, Debug, Clone)]
+pub struct Progress {
+    pub name: String,
+    pub total_size: String,
+    pub current_size: String,
+    pub speed: String,
+    pub progress: String,
+}
+
+/// Receives what the update logic reports while it runs.
+pub trait ProgressSink: Send + Sync {
+    fn progress(&self, progress: &Progress);
+
+    /// Files the player edited that the staged update would touch.
+    fn conflicts(&self, _conflicts: &[Conflict]) {}
+
+    /// The archives an update is about to download.
+    fn route(&self, _route: &UpdateRoute) {}
+}
+
+/// Drops every event; the log still says what is happening.
+pub struct SilentSink;
+
+impl ProgressSink for SilentSink {
+    fn progress(&self, _progress: &Progress) {}
+}
+
+pub fn report_progress(progress: Progress) {
+    core().sink.progress(&progress);
+}
@@ -0,0 +1,127 @@
+use crate::events::{Progress, ProgressSink};
+use crate::install::Conflict;
+use crate::planner::UpdateRoute;
+use crate::prompt::{BoxFuture, Confirmation, HeadlessPrompter, PromptKey, Prompter};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+
+pub static GLOBAL_APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Question {
+    /// Only shown; nothing waits for it.
+    Notice,
+    /// Answered through `answer_prompt` with `true` for `ok` and `false` for `cancel`.
+    Confirm {
+        key: PromptKey,
+        ok: String,
+        cancel: String,
+    },
+}
+
+/// Payload of the `prompt` event.
+#[derive(Serialize, Debug, Clone)]
+pub struct Prompt {
+    pub id: u64,
+    pub title: String,
+    pub message: String,
+    #[serde(flatten)]
+    pub question: Question,
+}
+
+static NEXT_PROMPT_ID: AtomicU64 = AtomicU64::new(1);
+static PENDING_PROMPTS: Lazy<Mutex<HashMap<u64, oneshot::Sender<bool>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Forwards progress and prompts to the app window as events.
+pub struct TauriFrontend {
+    app_handle: AppHandle,
+    /// Answers prompts the window could not be asked.
+    fallback: HeadlessPrompter,
+}
+
+impl TauriFrontend {
+    pub fn new(app_handle: AppHandle) -> Self {
+        TauriFrontend {
+            app_handle,
+            fallback: HeadlessPrompter::from_env(),
+        }
+    }
+}
+
+impl ProgressSink for TauriFrontend {
+    fn progress(&self, progress: &Progress) {
+        self.app_handle.emit("download_progress", progress).unwrap();
+    }
+
+    fn conflicts(&self, conflicts: &[Conflict]) {
+        self.app_handle.emit("update_conflicts", conflicts).unwrap();
+    }
+
+    fn route(&self, route: &UpdateRoute) {
+        self.app_handle.emit("update_route", route).unwrap();
+    }
+}
+
+impl Prompter for TauriFrontend {
+    fn notify(&self, title: &str, message: &str) {
+        let prompt = Prompt {
+            id: NEXT_PROMPT_ID.fetch_add(1, Ordering::Relaxed),
+            title: title.to_string(),
+            message: message.to_string(),
+            question: Question::Notice,
+        };
+        self.app_handle.emit("prompt", &prompt).unwrap();
+    }
+
+    /// Asks the window and waits for `answer_prompt`.
+    fn confirm(&self, confirmation: Confirmation) -> BoxFuture<'_, bool> {
+        let key = confirmation.key;
+        let prompt = Prompt {
+            id: NEXT_PROMPT_ID.fetch_add(1, Ordering::Relaxed),
+            title: confirmation.title,
+            message: confirmation.message,
+            question: Question::Confirm {
+                key,
+                ok: confirmation.ok,
+                cancel: confirmation.cancel,
+            },
+        };
+        let (sender, receiver) = oneshot::channel();
+        PENDING_PROMPTS.lock().unwrap().insert(prompt.id, sender);
+        if self.app_handle.emit("prompt", &prompt).is_err() {
+            PENDING_PROMPTS.lock().unwrap().remove(&prompt.id);
+            let answer = self.fallback.answer(key);
+            return Box::pin(async move { answer });
+        }
+        // A dropped prompt counts as cancelled
+        Box::pin(async move { receiver.await.unwrap_or(false) })
+    }
+}
+
+/// Delivers the frontend's answer to the prompt with `id`.
+pub fn answer_prompt(id: u64, answer: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let sender = PENDING_PROMPTS
+        .lock()
+        .unwrap()
+        .remove(&id)
+        .ok_or_else(|| format!("No prompt {} is waiting for an answer", id))?;
+    sender
+        .send(answer)
+        .map_err(|_| format!("Prompt {} was abandoned", id).into())
+}
+
+/// Cancels every unanswered prompt, e.g. when the download is cancelled.
+pub fn cancel_prompts() {
+    PENDING_PROMPTS.lock().unwrap().clear();
+}
@@ -1,24 +1,294 @@
-use dotenvy_macro::dotenv;
-use once_cell::sync::OnceCell;
+use crate::core::core;
+use crate::install::FileOperation;
+use crate::planner::{plan_route, UpdateRoute};
+use crate::receipt::{hash_file, load_receipt};
+use crate::settings::{load_settings, Channel};
+use crate::signature::verify_signature;
+use crate::version::ModVersion;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, io::Read, path::Path};
-use tauri::AppHandle;
-use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
-use version_compare::{Cmp, Version};
-
-pub static GLOBAL_APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    io::Read,
+    path::Path,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct UpdateInfo {
-    pub latest: String,
-    pub least: String,
-    pub updates: HashMap<String, String>,
+    pub latest: ModVersion,
+    pub least: ModVersion,
+    pub updates: BTreeMap<ModVersion, UpdateArchive>,
+    /// Complete file list of each version, used to find files older versions left behind.
+    #[serde(default)]
+    pub file_lists: BTreeMap<ModVersion, FileList>,
+    /// Releases of the other channels, in the same shape. The top level is the stable channel.
+    #[serde(default)]
+    pub channels: HashMap<Channel, UpdateInfo>,
+    /// Full install archives of specific versions, keyed like `updates`. Listing the latest
+    /// release here with its size lets the planner weigh it against the incremental chain.
+    #[serde(default)]
+    pub full_archives: BTreeMap<ModVersion, UpdateArchive>,
 }
 
+impl UpdateInfo {
+    pub fn for_channel(
+        mut self,
+        channel: Channel,
+    ) -> Result<UpdateInfo, Box<dyn std::error::Error>> {
+        if channel == Channel::Stable {
+            self.channels.clear();
+            return Ok(self);
+        }
+        self.channels
+            .remove(&channel)
+            .ok_or_else(|| format!("No {} releases are published right now", channel.name()).into())
+    }
+
+    /// Full install archive for `version`. The latest release always has the channel's archive.
+    pub fn full_archive(&self, version: &ModVersion, channel: Channel) -> Option<UpdateArchive> {
+        self.full_archives.get(version).cloned().or_else(|| {
+            (*version == self.latest).then(|| UpdateArchive::from_path(channel.full_archive()))
+        })
+    }
+
+    /// Every version the manifest can install, oldest first.
+    pub fn versions(&self) -> Vec<ModVersion> {
+        let versions: BTreeSet<&ModVersion> = self
+            .updates
+            .keys()
+            .chain(self.full_archives.keys())
+            .chain(std::iter::once(&self.latest))
+            .collect();
+        versions.into_iter().cloned().collect()
+    }
+
+    /// Incremental updates that take `current` to `target`, oldest first.
+    pub fn update_chain(
+        &self,
+        current: &ModVersion,
+        target: &ModVersion,
+    ) -> Vec<(ModVersion, UpdateArchive)> {
+        self.updates
+            .iter()
+            .filter(|(version, _)| current < *version && *version <= target)
+            .map(|(version, archive)| (version.clone(), archive.clone()))
+            .collect()
+    }
+}
+
+/// A JSON array of every install-relative path a version ships, hosted next to the updates.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FileList {
+    pub path: String,
+    pub sha256: String,
+}
+
+/// A single update archive from the manifest. Entries can be either a bare path
+/// (`"v1.2": "/updates/v1.2.zip"`) or an object with integrity information.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct UpdateStatus {
-    status: String,
-    message: String,
+#[serde(try_from = "ManifestEntry")]
+pub struct UpdateArchive {
+    pub path: String,
+    pub sha256: Option<String>,
+    pub size: Option<u64>,
+    pub description: Option<String>,
+    /// Detached minisign signature of the archive itself.
+    pub signature: Option<String>,
+    /// Deletes and renames applied after the archive is extracted.
+    pub operations: Vec<FileOperation>,
+}
+
+impl UpdateArchive {
+    /// An archive known only by its Dropbox path, without integrity information.
+    pub fn from_path(path: &str) -> Self {
+        UpdateArchive {
+            path: path.to_string(),
+            sha256: None,
+            size: None,
+            description: None,
+            signature: None,
+            operations: Vec::new(),
+        }
+    }
+
+    /// The archive's file name, the part of `path` after the last `/`.
+    pub fn file_name(&self) -> &str {
+        self.path
+            .rsplit_once('/')
+            .map_or(self.path.as_str(), |(_, name)| name)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestEntry {
+    Path(String),
+    Detailed {
+        path: String,
+        #[serde(default)]
+        sha256: Option<String>,
+        #[serde(default)]
+        size: Option<u64>,
+        #[serde(default)]
+        description: Option<String>,
+        #[serde(default)]
+        signature: Option<String>,
+        #[serde(default)]
+        operations: Vec<FileOperation>,
+    },
+}
+
+/// Manifest paths are absolute Dropbox paths like `/updates/v1.2.zip`.
+impl TryFrom<ManifestEntry> for UpdateArchive {
+    type Error = String;
+
+    fn try_from(entry: ManifestEntry) -> Result<Self, Self::Error> {
+        let archive = match entry {
+            ManifestEntry::Path(path) => UpdateArchive::from_path(&path),
+            ManifestEntry::Detailed {
+                path,
+                sha256,
+                size,
+                description,
+                signature,
+                operations,
+            } => UpdateArchive {
+                path,
+                sha256,
+                size,
+                description,
+                signature,
+                operations,
+            },
+        };
+        if !archive.path.starts_with('/') || archive.file_name().is_empty() {
+            return Err(format!(
+                "Invalid archive path \"{}\", expected a path like /updates/v1.2.zip",
+                archive.path
+            ));
+        }
+        Ok(archive)
+    }
+}
+
+/// Where an install stands against the manifest of its selected channel.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStatus {
+    NotInstalled,
+    UpToDate,
+    Available,
+    /// Too old to update incrementally; see `migrate::migrate_install`.
+    Unsupported,
+    /// The install came from a less stable channel than the one selected, so it has to be
+    /// replaced with the selected channel's latest release.
+    ChannelSwitch,
+    /// The installed version is newer than anything the manifest publishes (e.g. a preview build).
+    AheadOfManifest,
+}
+
+/// One archive of a planned update, in download order.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedArchive {
+    pub version: ModVersion,
+    pub path: String,
+    pub size: Option<u64>,
+    pub description: Option<String>,
+    /// A full install archive rather than an incremental update.
+    pub full: bool,
+}
+
+/// The result of an update check. The versions are `None` when nothing is installed.
+#[derive(Serialize, Debug, Clone)]
+pub struct UpdatePlan {
+    pub status: UpdateStatus,
+    pub current: Option<ModVersion>,
+    /// The channel's latest release, or the pinned version.
+    pub latest: Option<ModVersion>,
+    /// `latest` is the version the player pinned.
+    pub pinned: bool,
+    pub least: Option<ModVersion>,
+    pub installed_channel: Option<Channel>,
+    pub channel: Channel,
+    /// What an `Available` update downloads.
+    pub archives: Vec<PlannedArchive>,
+    /// Bytes to download, `None` when the manifest doesn't list every archive's size.
+    pub total_size: Option<u64>,
+    #[serde(skip)]
+    pub route: Option<UpdateRoute>,
+}
+
+impl UpdatePlan {
+    /// Title and message telling the player where their install stands.
+    pub fn summary(&self) -> (&'static str, String) {
+        let current = self
+            .current
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        let latest = self
+            .latest
+            .as_ref()
+            .map(|v| v.to_string())
+            .unwrap_or_default();
+        match self.status {
+            UpdateStatus::NotInstalled => (
+                "Not installed",
+                "No Convergence install was found in this directory".to_string(),
+            ),
+            UpdateStatus::ChannelSwitch => (
+                "Release channel changed",
+                format!(
+                    "You are on the {} build {}. Switching to {} reinstalls {}.",
+                    self.installed_channel.unwrap_or_default().name(),
+                    current,
+                    self.channel.name(),
+                    latest
+                ),
+            ),
+            UpdateStatus::Unsupported => (
+                "Unsupported Version",
+                format!(
+                    "You're using an older version: {} that can't be updated directly (oldest updatable version: {})\nUpdating will replace it with a fresh install of {}, keeping your settings and saves.",
+                    current,
+                    self.least.as_ref().map(|v| v.to_string()).unwrap_or_default(),
+                    latest
+                ),
+            ),
+            UpdateStatus::Available => (
+                "New version available",
+                format!(
+                    "A new version is available: {} (current: {})\nDownload: {}",
+                    latest,
+                    current,
+                    self.route
+                        .as_ref()
+                        .map(|route| route.describe())
+                        .unwrap_or_default()
+                ),
+            ),
+            UpdateStatus::UpToDate => (
+                "New version available",
+                format!("You are using the latest version: {}", current),
+            ),
+            UpdateStatus::AheadOfManifest if self.pinned => (
+                "Newer than pinned version",
+                format!(
+                    "You are using {}, which is newer than the version you pinned: {}.",
+                    current, latest
+                ),
+            ),
+            UpdateStatus::AheadOfManifest => (
+                "Newer than latest release",
+                format!(
+                    "You are using a newer version: {}, latest: {}.",
+                    current, latest
+                ),
+            ),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,37 +300,37 @@ pub struct DropboxResponse {
     pub link: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metadata {
     #[serde(rename = "name")]
-    name: String,
+    pub name: String,
 
     #[serde(rename = "path_lower")]
-    path_lower: String,
+    pub path_lower: String,
 
     #[serde(rename = "path_display")]
-    path_display: String,
+    pub path_display: String,
 
     #[serde(rename = "id")]
-    id: String,
+    pub id: String,
 
     #[serde(rename = "client_modified")]
-    client_modified: String,
+    pub client_modified: String,
 
     #[serde(rename = "server_modified")]
-    server_modified: String,
+    pub server_modified: String,
 
     #[serde(rename = "rev")]
-    rev: String,
+    pub rev: String,
 
     #[serde(rename = "size")]
-    size: i64,
+    pub size: i64,
 
     #[serde(rename = "is_downloadable")]
-    is_downloadable: bool,
+    pub is_downloadable: bool,
 
     #[serde(rename = "content_hash")]
-    content_hash: String,
+    pub content_hash: String,
 }
 
 pub fn format_size(bytes: u64) -> String {
@@ -95,27 +365,76 @@ pub fn format_speed(bytes_per_sec: f64) -> String {
     }
 }
 
-pub async fn check_updates(
-    downloading: bool,
-    directory: &str,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let updates_url = dotenv!("UPDATES_URL");
-    println!("Checking for updates...");
-    let file_path = Path::new(directory).join("version.txt");
+/// Dropbox's `content_hash`: the SHA-256 of the concatenated SHA-256 digests of every 4 MB block.
+pub async fn dropbox_content_hash(path: &Path) -> std::io::Result<String> {
+    const BLOCK_SIZE: u64 = 4 * 1024 * 1024;
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(path)?;
+        let mut block = Vec::with_capacity(BLOCK_SIZE as usize);
+        let mut overall = Sha256::new();
+        loop {
+            block.clear();
+            let read = (&mut file).take(BLOCK_SIZE).read_to_end(&mut block)?;
+            if read == 0 {
+                break;
+            }
+            overall.update(Sha256::digest(&block));
+        }
+        Ok(format!("{:x}", overall.finalize()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
 
-    if !file_path.exists() {
-        println!("Version file does not exist, skipping update check.");
-        return Ok([].to_vec());
-    }
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path).map(|(_, sha256)| sha256))
+        .await
+        .map_err(std::io::Error::other)?
+}
 
-    let version_string = File::open(file_path)
-        .expect("Failed to open version file")
-        .bytes()
-        .map(|b| b.expect("Failed to read byte") as char)
-        .collect::<String>();
+#[derive(Serialize, Debug, Clone)]
+pub struct VersionInfo {
+    pub version: ModVersion,
+    pub description: Option<String>,
+    pub installed: bool,
+    pub latest: bool,
+    pub pinned: bool,
+}
+
+/// Lists every version the selected channel's manifest knows about.
+pub async fn list_versions(
+    directory: &str,
+) -> Result<Vec<VersionInfo>, Box<dyn std::error::Error>> {
+    let settings = load_settings(Path::new(directory));
+    let installed = match load_receipt(Path::new(directory))? {
+        Some(receipt) => Some(receipt.installed_version()?),
+        None => None,
+    };
+    let update_info = fetch_manifest().await?.for_channel(settings.channel)?;
+    Ok(update_info
+        .versions()
+        .into_iter()
+        .map(|version| VersionInfo {
+            description: update_info
+                .updates
+                .get(&version)
+                .and_then(|archive| archive.description.clone()),
+            installed: installed.as_ref() == Some(&version),
+            latest: version == update_info.latest,
+            pinned: settings.pinned_version.as_ref() == Some(&version),
+            version,
+        })
+        .collect())
+}
+
+/// Downloads the update manifest and checks its signature.
+pub async fn fetch_manifest() -> Result<UpdateInfo, Box<dyn std::error::Error>> {
+    let updates_url = core().endpoints.updates_url.clone();
     let client = reqwest::Client::new();
     println!("Getting update info");
-    let response = client.get(updates_url).send().await;
+    let response = client.get(&updates_url).send().await;
 
     if let Err(err) = response {
         eprintln!("Error getting update info: {}", err);
@@ -126,101 +445,113 @@ pub async fn check_updates(
         eprintln!("Error getting update info: {}", response.status());
         return Err(Box::from(format!("HTTP error: {}", response.status())));
     }
-    let update_info: UpdateInfo = response
-        .json()
-        .await
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error>)?;
-
-    let current = Version::from(version_string.as_str()).unwrap();
-    let latest = Version::from(update_info.latest.as_str()).unwrap();
-    let least = Version::from(update_info.least.as_str()).unwrap();
-    let latest_str = update_info.latest.clone();
-
-    if current.compare_to(least, Cmp::Lt) {
-        let least_str = update_info.least.clone();
-
-        if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-            app_handle
-                .dialog()
-                .message(format!(
-                    "You're using an older version: {} that is not supported by the downloader (last supported version: {})\n Your version can't be updated.",
-                    current, least_str
-                ))
-                .kind(MessageDialogKind::Info)
-                .title("Unsupported Version")
-                .blocking_show();
-        }
+    let manifest = response.bytes().await?;
 
-        return Ok([].to_vec());
+    // The manifest decides what gets extracted into the game directory, so refuse it unless signed
+    let signature_url = format!("{}.minisig", updates_url);
+    let signature = client.get(&signature_url).send().await?;
+    if !signature.status().is_success() {
+        eprintln!("Error getting update signature: {}", signature.status());
+        return Err(Box::from(
+            "The update manifest is not signed. Refusing to install updates.",
+        ));
     }
+    let signature = signature.text().await?;
+    verify_signature(&manifest, &signature).map_err(|e| {
+        eprintln!("Update manifest signature check failed: {}", e);
+        format!(
+            "The update manifest failed signature verification ({}). Refusing to install updates.",
+            e
+        )
+    })?;
 
-    match current.compare(latest) {
-        Cmp::Lt => {
-            if !downloading {
-                if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                    app_handle
-                        .dialog()
-                        .message(format!(
-                            "A new version is available: {} (current: {})",
-                            latest_str, current
-                        ))
-                        .kind(MessageDialogKind::Info)
-                        .title("New version available")
-                        .blocking_show();
-                }
-            }
-            let mut updates: Vec<String> = Vec::new();
-            for (key, value) in update_info.updates.iter() {
-                let replaced = key.replace("v", "");
-                let version = Version::from(replaced.as_str()).unwrap();
-                if current < version {
-                    updates.push(value.to_string());
-                }
-            }
-            updates.sort_by(|a, b| {
-                let a_str = a.replace("v", "");
-                let version_a = Version::from(a_str.as_str()).unwrap();
-                let b_str = b.replace("v", "");
-                let version_b = Version::from(b_str.as_str()).unwrap();
-                match version_a.compare(version_b) {
-                    Cmp::Lt => std::cmp::Ordering::Less,
-                    Cmp::Eq => std::cmp::Ordering::Equal,
-                    Cmp::Gt => std::cmp::Ordering::Greater,
-                    _ => unreachable!(),
-                }
-            });
-
-            Ok(updates)
-        }
-        Cmp::Eq => {
-            if !downloading {
-                if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                    app_handle
-                        .dialog()
-                        .message(format!("You are using the latest version: {}", current))
-                        .kind(MessageDialogKind::Info)
-                        .title("New version available")
-                        .blocking_show();
-                }
-            }
-            Ok([].to_vec())
+    serde_json::from_slice(&manifest).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+}
+
+/// Parses `version` and checks that the channel's manifest can install it.
+pub async fn published_version(
+    version: &str,
+    channel: Channel,
+) -> Result<ModVersion, Box<dyn std::error::Error>> {
+    let version: ModVersion = version.parse()?;
+    let update_info = fetch_manifest().await?.for_channel(channel)?;
+    if !update_info.versions().contains(&version) {
+        return Err(format!("{} is not a published {} version", version, channel.name()).into());
+    }
+    Ok(version)
+}
+
+/// Builds the update plan for the install in `directory`. Only reads the install and
+/// the manifest; deciding what to show or download is up to the caller.
+pub async fn check_updates(directory: &str) -> Result<UpdatePlan, Box<dyn std::error::Error>> {
+    println!("Checking for updates...");
+    let settings = load_settings(Path::new(directory));
+    let channel = settings.channel;
+    let mut plan = UpdatePlan {
+        status: UpdateStatus::NotInstalled,
+        current: None,
+        latest: None,
+        pinned: false,
+        least: None,
+        installed_channel: None,
+        channel,
+        archives: Vec::new(),
+        total_size: None,
+        route: None,
+    };
+    let Some(receipt) = load_receipt(Path::new(directory))? else {
+        println!("No install receipt found, skipping update check.");
+        return Ok(plan);
+    };
+    let current = receipt.installed_version()?;
+    let update_info = fetch_manifest().await?.for_channel(channel)?;
+    // The manifest's latest stays as published so its full archive isn't mistaken for the pin's
+    plan.pinned = settings.pinned_version.is_some();
+    let target = match settings.pinned_version {
+        Some(pin) => {
+            println!("Pinned to {}, not offering newer versions", pin);
+            pin
         }
-        Cmp::Gt => {
-            if !downloading {
-                if let Some(app_handle) = GLOBAL_APP_HANDLE.get() {
-                    app_handle
-                        .dialog()
-                        .message(format!(
-                            "You are using a newer version: {}, latest: {}.\n How..???",
-                            current, latest_str
-                        ))
-                        .kind(MessageDialogKind::Info)
-                        .title("New version available")
-                        .blocking_show();
-                }
-            }
-            panic!("You are using a newer version than expected")
+        None => update_info.latest.clone(),
+    };
+    plan.current = Some(current.clone());
+    plan.latest = Some(target.clone());
+    plan.least = Some(update_info.least.clone());
+    plan.installed_channel = Some(receipt.channel);
+
+    plan.status = if channel < receipt.channel {
+        UpdateStatus::ChannelSwitch
+    } else if current < update_info.least {
+        UpdateStatus::Unsupported
+    } else {
+        match current.cmp(&target) {
+            Ordering::Less => UpdateStatus::Available,
+            Ordering::Equal => UpdateStatus::UpToDate,
+            Ordering::Greater => UpdateStatus::AheadOfManifest,
         }
-        _ => unreachable!(),
+    };
+    if plan.status == UpdateStatus::Available {
+        let Some(route) = plan_route(&update_info, Some(&current), &target, channel) else {
+            return Err(
+                format!("No update path from {} to {} is published", current, target).into(),
+            );
+        };
+        println!("Update route to {}: {}", target, route.describe());
+        plan.archives = route
+            .full
+            .iter()
+            .map(|archive| (archive, true))
+            .chain(route.updates.iter().map(|archive| (archive, false)))
+            .map(|((version, archive), full)| PlannedArchive {
+                version: version.clone(),
+                path: archive.path.clone(),
+                size: archive.size,
+                description: archive.description.clone(),
+                full,
+            })
+            .collect();
+        plan.total_size = route.total_size;
+        plan.route = Some(route);
     }
+    Ok(plan)
 }
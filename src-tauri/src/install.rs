@@ -0,0 +1,1234 @@
+use crate::receipt::{
+    hash_file, load_receipt, receipt_path, save_receipt, unix_time, Receipt, ReceiptFile,
+};
+use crate::settings::{load_settings, ConflictPolicy};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// Bookkeeping directory kept inside the install directory, so staged files can be
+/// renamed into place without crossing filesystems.
+pub const STATE_DIR: &str = ".er-downloader";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "phase")]
+enum Journal {
+    /// Archives are being extracted into the staging directory. Nothing in the
+    /// install has been touched yet, so an interruption here is rolled back.
+    Staging { version: Option<String> },
+    /// Staged files are being moved into place. An interruption here is rolled
+    /// forward, or rolled back if the staged files are no longer usable.
+    Applying {
+        version: Option<String>,
+        previous_version: Option<String>,
+        files: Vec<JournalEntry>,
+        /// Repairs restore files of the installed version, so they don't get a snapshot.
+        #[serde(default)]
+        repair: bool,
+        #[serde(default)]
+        full_install: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct JournalEntry {
+    path: String,
+    existed: bool,
+    /// Manifest path of the archive the staged file came from.
+    #[serde(default)]
+    archive: Option<String>,
+    /// The file belonged to the game rather than an earlier install of the mod.
+    #[serde(default)]
+    vanilla: bool,
+    /// The update deletes the file instead of installing it.
+    #[serde(default)]
+    removed: bool,
+}
+
+/// A change an update makes to files it doesn't ship, listed in the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FileOperation {
+    Delete { path: String },
+    Rename { from: String, to: String },
+}
+
+/// Windows ignores case and drops trailing dots and spaces, so `.ER-Downloader.` is the
+/// same folder as `.er-downloader`.
+fn is_state_dir(name: &str) -> bool {
+    name.trim_end_matches(['.', ' '])
+        .eq_ignore_ascii_case(STATE_DIR)
+}
+
+/// Normalizes a manifest path to a `/`-separated path inside the install directory,
+/// refusing anything that could point outside of it or into the downloader's own state.
+pub fn install_relative_path(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let parts: Vec<&str> = path
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    let unsafe_path = path.starts_with(['/', '\\'])
+        || parts.is_empty()
+        || parts.iter().any(|part| *part == ".." || part.contains(':'))
+        || is_state_dir(parts[0]);
+    if unsafe_path {
+        return Err(format!(
+            "Refusing to touch {}, it is outside the install directory",
+            path
+        )
+        .into());
+    }
+    Ok(parts.join("/"))
+}
+
+pub fn state_dir(install_dir: &Path) -> PathBuf {
+    install_dir.join(STATE_DIR)
+}
+
+fn staging_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("staging")
+}
+
+fn transaction_backup_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("transaction-backup")
+}
+
+fn backups_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("backups")
+}
+
+/// Original game files the mod overwrote, restored by `uninstall_mod`.
+pub fn vanilla_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("vanilla")
+}
+
+fn journal_path(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("journal.json")
+}
+
+/// The receipt as it was before the update being applied, kept for the snapshot.
+fn previous_receipt_path(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("previous-receipt.json")
+}
+
+/// Runs install work such as committing a transaction, which hashes every applied file,
+/// off the async runtime.
+pub async fn run_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, Box<dyn std::error::Error>> + Send + 'static,
+) -> Result<T, Box<dyn std::error::Error>> {
+    tokio::task::spawn_blocking(move || work().map_err(|e| e.to_string()))
+        .await?
+        .map_err(|e| e.into())
+}
+
+/// Writes `data` next to `path` and renames it over the original, so readers only
+/// ever see the old or the new contents.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    File::open(&tmp_path)?.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+pub fn read_version_file(install_dir: &Path) -> Option<String> {
+    fs::read_to_string(install_dir.join("version.txt"))
+        .ok()
+        .map(|version| version.trim().to_string())
+}
+
+fn write_journal(install_dir: &Path, journal: &Journal) -> Result<(), Box<dyn std::error::Error>> {
+    write_atomic(
+        &journal_path(install_dir),
+        &serde_json::to_vec_pretty(journal)?,
+    )?;
+    Ok(())
+}
+
+fn read_journal(install_dir: &Path) -> Result<Option<Journal>, Box<dyn std::error::Error>> {
+    let path = journal_path(install_dir);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// Lists every file below `dir` as a `/`-separated path relative to `dir`.
+pub fn list_files(dir: &Path) -> std::io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                let relative = path.strip_prefix(dir).unwrap_or(&path);
+                let parts: Vec<_> = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect();
+                files.push(parts.join("/"));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The `/`-separated path a zip entry extracts to, or `None` for directories. Entries
+/// that would escape the install directory or overwrite the downloader's own state are
+/// refused like manifest operations.
+fn entry_path(
+    entry: &zip::read::ZipFile<File>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if entry.is_dir() {
+        return Ok(None);
+    }
+    if entry.enclosed_name().is_none() {
+        return Err(format!(
+            "Refusing to extract {}, it is outside the install directory",
+            entry.name()
+        )
+        .into());
+    }
+    install_relative_path(entry.name()).map(Some)
+}
+
+pub fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+}
+
+fn remove_dir_if_exists(dir: &Path) -> std::io::Result<()> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// A staged file that would overwrite a file the player changed since it was installed.
+#[derive(Serialize, Debug, Clone)]
+pub struct Conflict {
+    pub path: String,
+    pub policy: ConflictPolicy,
+}
+
+/// A downloaded update archive and the deletes and renames that follow it.
+pub struct PendingArchive {
+    pub path: PathBuf,
+    /// Manifest path of the archive, recorded as the origin of its files.
+    pub name: String,
+    pub operations: Vec<FileOperation>,
+}
+
+/// Where a file in a merged set of updates comes from.
+enum StagedSource {
+    Entry {
+        archive: usize,
+        index: usize,
+    },
+    /// A file only in the install, renamed by one of the updates.
+    Installed(String),
+}
+
+/// An update being assembled in the staging directory. Nothing in the install
+/// directory changes until [`StagedUpdate::commit`] is called.
+pub struct StagedUpdate {
+    install_dir: PathBuf,
+    version: Option<String>,
+    /// Which archive each staged file was extracted from; later archives win.
+    origins: BTreeMap<String, String>,
+    /// Installed files the update deletes.
+    removed: BTreeSet<String>,
+    full_install: bool,
+}
+
+impl StagedUpdate {
+    pub fn begin(
+        install_dir: &Path,
+        version: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        recover_interrupted_update(install_dir)?;
+        remove_dir_if_exists(&staging_dir(install_dir))?;
+        fs::create_dir_all(staging_dir(install_dir))?;
+        let version = version.map(|v| v.to_string());
+        write_journal(
+            install_dir,
+            &Journal::Staging {
+                version: version.clone(),
+            },
+        )?;
+        Ok(StagedUpdate {
+            install_dir: install_dir.to_path_buf(),
+            version,
+            origins: BTreeMap::new(),
+            removed: BTreeSet::new(),
+            full_install: false,
+        })
+    }
+
+    /// The staged archives make up a whole install, so the receipt will list every file.
+    pub fn mark_full_install(&mut self) {
+        self.full_install = true;
+    }
+
+    /// Runs a step of the transaction on a blocking thread, since extracting and hashing
+    /// whole archives would otherwise stall the async runtime.
+    pub async fn run_step<T: Send + 'static>(
+        &mut self,
+        step: impl FnOnce(&mut StagedUpdate) -> Result<T, Box<dyn std::error::Error>> + Send + 'static,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let mut transaction = StagedUpdate {
+            install_dir: self.install_dir.clone(),
+            version: self.version.clone(),
+            origins: std::mem::take(&mut self.origins),
+            removed: std::mem::take(&mut self.removed),
+            full_install: self.full_install,
+        };
+        let (transaction, result) = tokio::task::spawn_blocking(move || {
+            let result = step(&mut transaction).map_err(|e| e.to_string());
+            (transaction, result)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+        *self = transaction;
+        result.map_err(|e| e.into())
+    }
+
+    pub fn stage_archive(
+        &mut self,
+        archive_path: &Path,
+        archive_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::open(archive_path)?;
+        let mut archive = zip::ZipArchive::new(file)?;
+        for i in 0..archive.len() {
+            if let Some(path) = entry_path(&archive.by_index(i)?)? {
+                self.removed.remove(&path);
+                self.origins.insert(path, archive_name.to_string());
+            }
+        }
+        archive.extract(staging_dir(&self.install_dir))?;
+        Ok(())
+    }
+
+    /// Stages several downloaded updates at once. The archives and their operations are
+    /// merged into one file set where the newest entry wins, and each resulting file is
+    /// written once; the outcome matches staging them one after another. Deletes and
+    /// renames of files that are only in the install copy them into staging or mark them
+    /// for removal, so the install directory is still untouched until commit.
+    pub fn stage_archives(
+        &mut self,
+        pending: &[PendingArchive],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut archives = Vec::new();
+        let mut files: BTreeMap<String, StagedSource> = BTreeMap::new();
+        for (number, update) in pending.iter().enumerate() {
+            let mut archive = zip::ZipArchive::new(File::open(&update.path)?)?;
+            for index in 0..archive.len() {
+                if let Some(path) = entry_path(&archive.by_index(index)?)? {
+                    self.removed.remove(&path);
+                    self.origins.insert(path.clone(), update.name.clone());
+                    files.insert(
+                        path,
+                        StagedSource::Entry {
+                            archive: number,
+                            index,
+                        },
+                    );
+                }
+            }
+            archives.push(archive);
+
+            for operation in &update.operations {
+                match operation {
+                    FileOperation::Delete { path } => {
+                        let path = install_relative_path(path)?;
+                        println!("Deleting {}", path);
+                        if files.remove(&path).is_some() {
+                            self.origins.remove(&path);
+                        }
+                        if self.install_dir.join(&path).exists() {
+                            self.removed.insert(path);
+                        }
+                    }
+                    FileOperation::Rename { from, to } => {
+                        let from = install_relative_path(from)?;
+                        let to = install_relative_path(to)?;
+                        println!("Renaming {} to {}", from, to);
+                        let installed = self.install_dir.join(&from).exists();
+                        let source = match files.remove(&from) {
+                            Some(source) => source,
+                            None if installed && !self.removed.contains(&from) => {
+                                StagedSource::Installed(from.clone())
+                            }
+                            None => {
+                                return Err(format!(
+                                    "Can't rename {}, the file doesn't exist",
+                                    from
+                                )
+                                .into())
+                            }
+                        };
+                        files.insert(to.clone(), source);
+                        if let Some(archive) = self.origins.remove(&from) {
+                            self.origins.insert(to.clone(), archive);
+                        }
+                        self.removed.remove(&to);
+                        if installed {
+                            self.removed.insert(from);
+                        }
+                    }
+                }
+            }
+        }
+
+        let staging = staging_dir(&self.install_dir);
+        for (path, source) in files {
+            let target = staging.join(&path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match source {
+                StagedSource::Entry { archive, index } => {
+                    let mut entry = archives[archive].by_index(index)?;
+                    std::io::copy(&mut entry, &mut File::create(&target)?)?;
+                }
+                StagedSource::Installed(from) => {
+                    fs::copy(self.install_dir.join(from), &target)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts only the given files from an archive, returning the ones it contained.
+    pub fn stage_files(
+        &mut self,
+        archive_path: &Path,
+        archive_name: &str,
+        wanted: &BTreeSet<String>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let file = File::open(archive_path)?;
+        let mut archive = zip::ZipArchive::new(file)?;
+        let staging = staging_dir(&self.install_dir);
+        let mut staged = Vec::new();
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let Some(path) = entry_path(&entry)?.filter(|path| wanted.contains(path)) else {
+                continue;
+            };
+            let target = staging.join(&path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            std::io::copy(&mut entry, &mut File::create(&target)?)?;
+            self.origins.insert(path.clone(), archive_name.to_string());
+            staged.push(path);
+        }
+        Ok(staged)
+    }
+
+    /// Path of a staged file, so callers can check it before committing.
+    pub fn staged_path(&self, path: &str) -> PathBuf {
+        staging_dir(&self.install_dir).join(path)
+    }
+
+    /// Staged files whose installed copy no longer matches the hash in the receipt.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>, Box<dyn std::error::Error>> {
+        let Some(receipt) = load_receipt(&self.install_dir)? else {
+            return Ok(Vec::new());
+        };
+        let settings = load_settings(&self.install_dir);
+        let mut conflicts = Vec::new();
+        for path in list_files(&staging_dir(&self.install_dir))? {
+            let Some(installed) = receipt.files.get(&path) else {
+                continue;
+            };
+            let target = self.install_dir.join(&path);
+            if !target.exists() {
+                continue;
+            }
+            let (size, sha256) = hash_file(&target)?;
+            if size != installed.size || sha256 != installed.sha256 {
+                conflicts.push(Conflict {
+                    policy: settings.conflict_policy(&path),
+                    path,
+                });
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Applies each conflict's policy to the staged copy, before anything is moved into place.
+    fn resolve_conflicts(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let staging = staging_dir(&self.install_dir);
+        for conflict in self.conflicts()? {
+            let staged = staging.join(&conflict.path);
+            match conflict.policy {
+                ConflictPolicy::TakeNew => {
+                    println!("Replacing modified file {}", conflict.path);
+                }
+                ConflictPolicy::Keep => {
+                    println!("Keeping modified file {}", conflict.path);
+                    fs::remove_file(&staged)?;
+                    self.origins.remove(&conflict.path);
+                }
+                ConflictPolicy::SaveAsNew => {
+                    let new_path = format!("{}.new", conflict.path);
+                    println!(
+                        "Keeping modified file {}, saving update as {}",
+                        conflict.path, new_path
+                    );
+                    fs::rename(&staged, staging.join(&new_path))?;
+                    if let Some(archive) = self.origins.remove(&conflict.path) {
+                        self.origins.insert(new_path, archive);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves every staged file into the install directory and writes the new version.
+    pub fn commit(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.resolve_conflicts()?;
+        self.apply(false)
+    }
+
+    /// Puts staged copies of damaged files back without touching the version or snapshots.
+    pub fn commit_repair(self) -> Result<(), Box<dyn std::error::Error>> {
+        self.apply(true)
+    }
+
+    fn apply(self, repair: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let previous_receipt = load_receipt(&self.install_dir)?;
+        // Installs migrated from version.txt don't know all their files, so an untracked file
+        // may be the mod's rather than the game's
+        let is_vanilla = |path: &str| match &previous_receipt {
+            None => true,
+            Some(receipt) => receipt.complete && !receipt.files.contains_key(path),
+        };
+        let staged = list_files(&staging_dir(&self.install_dir))?;
+        let mut files: Vec<JournalEntry> = staged
+            .into_iter()
+            .map(|path| {
+                let existed = self.install_dir.join(&path).exists();
+                JournalEntry {
+                    existed,
+                    archive: self.origins.get(&path).cloned(),
+                    vanilla: existed && is_vanilla(&path),
+                    removed: false,
+                    path,
+                }
+            })
+            .collect();
+        files.extend(self.removed.iter().map(|path| JournalEntry {
+            path: path.clone(),
+            existed: true,
+            archive: None,
+            vanilla: is_vanilla(path),
+            removed: true,
+        }));
+        if previous_receipt.is_some() && !repair {
+            fs::copy(
+                receipt_path(&self.install_dir),
+                previous_receipt_path(&self.install_dir),
+            )?;
+        }
+        let previous_version = previous_receipt.map(|receipt| receipt.version);
+        println!("Applying {} staged file(s)", files.len());
+        write_journal(
+            &self.install_dir,
+            &Journal::Applying {
+                version: self.version.clone(),
+                previous_version: previous_version.clone(),
+                files: files.clone(),
+                repair,
+                full_install: self.full_install,
+            },
+        )?;
+        finish_applying(
+            &self.install_dir,
+            self.version.as_deref(),
+            previous_version.as_deref(),
+            &files,
+            repair,
+            self.full_install,
+        )
+    }
+
+    /// Throws away the staged files; the install directory was never touched.
+    pub fn abort(self) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Discarding staged update");
+        cleanup(&self.install_dir)
+    }
+}
+
+fn apply_files(install_dir: &Path, files: &[JournalEntry]) -> std::io::Result<()> {
+    let staging = staging_dir(install_dir);
+    let backup = transaction_backup_dir(install_dir);
+    for entry in files {
+        let staged = staging.join(&entry.path);
+        let target = install_dir.join(&entry.path);
+        if entry.removed {
+            if target.exists() {
+                let backup_path = backup.join(&entry.path);
+                if backup_path.exists() {
+                    fs::remove_file(&target)?;
+                } else {
+                    move_file(&target, &backup_path)?;
+                }
+                remove_empty_parents(install_dir, &target);
+            }
+            continue;
+        }
+        if !staged.exists() {
+            // Already moved into place before an interruption
+            if !target.exists() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("Staged file {} is missing", entry.path),
+                ));
+            }
+            continue;
+        }
+        let backup_path = backup.join(&entry.path);
+        if target.exists() {
+            if entry.existed && !backup_path.exists() {
+                move_file(&target, &backup_path)?;
+            } else {
+                fs::remove_file(&target)?;
+            }
+        }
+        move_file(&staged, &target)?;
+    }
+    Ok(())
+}
+
+fn rollback_files(install_dir: &Path, files: &[JournalEntry]) -> std::io::Result<()> {
+    let staging = staging_dir(install_dir);
+    let backup = transaction_backup_dir(install_dir);
+    for entry in files.iter().rev() {
+        let target = install_dir.join(&entry.path);
+        let backup_path = backup.join(&entry.path);
+        if backup_path.exists() {
+            if target.exists() {
+                fs::remove_file(&target)?;
+            }
+            move_file(&backup_path, &target)?;
+        } else if !entry.existed && target.exists() && !staging.join(&entry.path).exists() {
+            fs::remove_file(&target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Records the applied files, with their hashes, in the install receipt.
+fn update_receipt(
+    install_dir: &Path,
+    version: Option<&str>,
+    files: &[JournalEntry],
+    repair: bool,
+    full_install: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Full installs of an unknown version fall back to the version.txt shipped in the archive
+    let version = version
+        .map(|v| v.to_string())
+        .or_else(|| read_version_file(install_dir));
+    // A version.txt the archive just installed isn't a legacy install to migrate
+    let existing = if receipt_path(install_dir).exists() {
+        load_receipt(install_dir)?
+    } else {
+        None
+    };
+    let mut receipt =
+        existing.unwrap_or_else(|| Receipt::new(version.as_deref().unwrap_or("unknown")));
+    let now = unix_time();
+    for entry in files {
+        if entry.removed {
+            receipt.files.remove(&entry.path);
+            continue;
+        }
+        let (size, sha256) = hash_file(&install_dir.join(&entry.path))?;
+        receipt.files.insert(
+            entry.path.clone(),
+            ReceiptFile {
+                size,
+                sha256,
+                archive: entry.archive.clone().unwrap_or_default(),
+                installed_at: now,
+            },
+        );
+    }
+    if let Some(version) = version {
+        receipt.version = version;
+    }
+    if !repair {
+        receipt.channel = load_settings(install_dir).channel;
+    }
+    if full_install {
+        receipt.complete = true;
+    }
+    receipt.updated_at = now;
+    save_receipt(install_dir, &receipt)?;
+    println!("Install receipt updated: {}", receipt.version);
+    Ok(())
+}
+
+/// Keeps a copy of every game file the update overwrote for the first time.
+fn save_vanilla_files(
+    install_dir: &Path,
+    files: &[JournalEntry],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let backup = transaction_backup_dir(install_dir);
+    let vanilla = vanilla_dir(install_dir);
+    for entry in files.iter().filter(|entry| entry.vanilla) {
+        let original = backup.join(&entry.path);
+        let target = vanilla.join(&entry.path);
+        if original.exists() && !target.exists() {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&original, &target)?;
+            println!("Backed up original game file {}", entry.path);
+        }
+    }
+    Ok(())
+}
+
+fn finish_applying(
+    install_dir: &Path,
+    version: Option<&str>,
+    previous_version: Option<&str>,
+    files: &[JournalEntry],
+    repair: bool,
+    full_install: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Err(e) = apply_files(install_dir, files) {
+        eprintln!("Applying update failed, rolling back: {}", e);
+        rollback_files(install_dir, files)?;
+        cleanup(install_dir)?;
+        return Err(format!("Applying update failed and was rolled back: {}", e).into());
+    }
+    save_vanilla_files(install_dir, files)?;
+    update_receipt(install_dir, version, files, repair, full_install)?;
+    if !repair {
+        save_snapshot(install_dir, previous_version, version, files)?;
+    }
+    cleanup(install_dir)
+}
+
+/// What an update replaced and added, so `rollback_update` can restore the previous version.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub previous_version: Option<String>,
+    pub version: Option<String>,
+    pub created_at: u64,
+    /// Files that existed before the update; their old contents are kept under `files/`.
+    pub replaced: Vec<String>,
+    /// Files the update created, removed again on rollback.
+    pub added: Vec<String>,
+}
+
+fn snapshot_name(previous_version: Option<&str>) -> String {
+    previous_version
+        .unwrap_or("none")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn save_snapshot(
+    install_dir: &Path,
+    previous_version: Option<&str>,
+    version: Option<&str>,
+    files: &[JournalEntry],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let retention = load_settings(install_dir).backup_retention;
+    if retention == 0 {
+        return Ok(());
+    }
+
+    let snapshot_dir = backups_dir(install_dir).join(snapshot_name(previous_version));
+    remove_dir_if_exists(&snapshot_dir)?;
+    fs::create_dir_all(&snapshot_dir)?;
+    let transaction_backup = transaction_backup_dir(install_dir);
+    if transaction_backup.exists() {
+        fs::rename(&transaction_backup, snapshot_dir.join("files"))?;
+    }
+    let previous_receipt = previous_receipt_path(install_dir);
+    if previous_receipt.exists() {
+        fs::rename(&previous_receipt, snapshot_dir.join("receipt.json"))?;
+    }
+
+    let snapshot = Snapshot {
+        previous_version: previous_version.map(|v| v.to_string()),
+        version: version.map(|v| v.to_string()),
+        created_at: unix_time(),
+        replaced: files
+            .iter()
+            .filter(|entry| entry.existed)
+            .map(|entry| entry.path.clone())
+            .collect(),
+        added: files
+            .iter()
+            .filter(|entry| !entry.existed)
+            .map(|entry| entry.path.clone())
+            .collect(),
+    };
+    write_atomic(
+        &snapshot_dir.join("snapshot.json"),
+        &serde_json::to_vec_pretty(&snapshot)?,
+    )?;
+    println!(
+        "Saved snapshot of {} file(s) from {}",
+        snapshot.replaced.len(),
+        previous_version.unwrap_or("a fresh install")
+    );
+    prune_snapshots(install_dir, retention)
+}
+
+/// Every snapshot in the install, newest first.
+pub fn list_snapshots(
+    install_dir: &Path,
+) -> Result<Vec<(PathBuf, Snapshot)>, Box<dyn std::error::Error>> {
+    let dir = backups_dir(install_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Ok(data) = fs::read(path.join("snapshot.json")) else {
+            continue;
+        };
+        let snapshot: Snapshot = serde_json::from_slice(&data)?;
+        snapshots.push((path, snapshot));
+    }
+    snapshots.sort_by_key(|(_, snapshot)| std::cmp::Reverse(snapshot.created_at));
+    Ok(snapshots)
+}
+
+fn prune_snapshots(install_dir: &Path, retention: usize) -> Result<(), Box<dyn std::error::Error>> {
+    for (path, snapshot) in list_snapshots(install_dir)?.into_iter().skip(retention) {
+        println!(
+            "Pruning snapshot of {}",
+            snapshot
+                .previous_version
+                .as_deref()
+                .unwrap_or("a fresh install")
+        );
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// Removes `path`'s parent directories below `install_dir` as long as they are empty.
+pub fn remove_empty_parents(install_dir: &Path, path: &Path) {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == install_dir || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        current = dir.parent();
+    }
+}
+
+/// Restores the install to how it was before the most recent update.
+pub fn rollback_update(install_dir: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    recover_interrupted_update(install_dir)?;
+    let Some((snapshot_dir, snapshot)) = list_snapshots(install_dir)?.into_iter().next() else {
+        return Err("There is no previous version to roll back to".into());
+    };
+    println!(
+        "Rolling back {} to {}",
+        snapshot.version.as_deref().unwrap_or("the current install"),
+        snapshot
+            .previous_version
+            .as_deref()
+            .unwrap_or("a fresh install")
+    );
+
+    for path in &snapshot.added {
+        let target = install_dir.join(path);
+        if target.exists() {
+            fs::remove_file(&target)?;
+            remove_empty_parents(install_dir, &target);
+        }
+    }
+    let files = snapshot_dir.join("files");
+    for path in &snapshot.replaced {
+        let saved = files.join(path);
+        if saved.exists() {
+            let target = install_dir.join(path);
+            if target.exists() {
+                fs::remove_file(&target)?;
+            }
+            move_file(&saved, &target)?;
+        }
+    }
+    let saved_receipt = snapshot_dir.join("receipt.json");
+    if saved_receipt.exists() {
+        move_file(&saved_receipt, &receipt_path(install_dir))?;
+    } else if receipt_path(install_dir).exists() {
+        fs::remove_file(receipt_path(install_dir))?;
+    }
+    fs::remove_dir_all(snapshot_dir)?;
+    Ok(snapshot.previous_version)
+}
+
+fn cleanup(install_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    remove_dir_if_exists(&staging_dir(install_dir))?;
+    remove_dir_if_exists(&transaction_backup_dir(install_dir))?;
+    for path in [
+        journal_path(install_dir),
+        previous_receipt_path(install_dir),
+    ] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Drops the receipt, snapshots and saved game originals so the next install starts
+/// from scratch. Settings and the cleanup log are kept.
+pub fn forget_install(install_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    cleanup(install_dir)?;
+    remove_dir_if_exists(&backups_dir(install_dir))?;
+    remove_dir_if_exists(&vanilla_dir(install_dir))?;
+    let receipt = receipt_path(install_dir);
+    if receipt.exists() {
+        fs::remove_file(receipt)?;
+    }
+    Ok(())
+}
+
+/// Finishes or undoes an update that was interrupted by a crash or a closed app.
+pub fn recover_interrupted_update(install_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    match read_journal(install_dir)? {
+        None => Ok(()),
+        Some(Journal::Staging { .. }) => {
+            println!("Found an interrupted update that was still staging, rolling it back");
+            cleanup(install_dir)
+        }
+        Some(Journal::Applying {
+            version,
+            previous_version,
+            files,
+            repair,
+            full_install,
+        }) => {
+            println!("Found an interrupted update that was being applied, rolling it forward");
+            finish_applying(
+                install_dir,
+                version.as_deref(),
+                previous_version.as_deref(),
+                &files,
+                repair,
+                full_install,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    /// What a staged update would do to the install: staged files with their contents,
+    /// installed files it removes, and the archive each staged file came from.
+    type Outcome = (
+        BTreeMap<String, Vec<u8>>,
+        BTreeSet<String>,
+        BTreeMap<String, String>,
+    );
+
+    /// An update archive's files with their contents, and its operations.
+    type TestArchive<'a> = (&'a [(&'a str, &'a str)], Vec<FileOperation>);
+
+    fn delete(path: &str) -> FileOperation {
+        FileOperation::Delete {
+            path: path.to_string(),
+        }
+    }
+
+    fn rename(from: &str, to: &str) -> FileOperation {
+        FileOperation::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    /// Applies an archive's operations to the staging directory the way updates were staged
+    /// before they were merged, one archive at a time.
+    fn stage_operations(
+        transaction: &mut StagedUpdate,
+        operations: &[FileOperation],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let install_dir = transaction.install_dir.clone();
+        let staging = staging_dir(&install_dir);
+        for operation in operations {
+            match operation {
+                FileOperation::Delete { path } => {
+                    let path = install_relative_path(path)?;
+                    let staged = staging.join(&path);
+                    if staged.exists() {
+                        fs::remove_file(staged)?;
+                        transaction.origins.remove(&path);
+                    }
+                    if install_dir.join(&path).exists() {
+                        transaction.removed.insert(path);
+                    }
+                }
+                FileOperation::Rename { from, to } => {
+                    let from = install_relative_path(from)?;
+                    let to = install_relative_path(to)?;
+                    let staged = staging.join(&from);
+                    let target = staging.join(&to);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    if staged.exists() {
+                        fs::rename(&staged, &target)?;
+                    } else if install_dir.join(&from).exists()
+                        && !transaction.removed.contains(&from)
+                    {
+                        fs::copy(install_dir.join(&from), &target)?;
+                    } else {
+                        return Err(format!("Can't rename {}, the file doesn't exist", from).into());
+                    }
+                    if let Some(archive) = transaction.origins.remove(&from) {
+                        transaction.origins.insert(to.clone(), archive);
+                    }
+                    transaction.removed.remove(&to);
+                    if install_dir.join(&from).exists() {
+                        transaction.removed.insert(from);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes each archive next to the install and returns them ready to stage.
+    fn pending_archives(dir: &Path, archives: &[TestArchive]) -> Vec<PendingArchive> {
+        archives
+            .iter()
+            .enumerate()
+            .map(|(number, (files, operations))| {
+                let name = format!("update-{}.zip", number);
+                let path = dir.join(&name);
+                let mut zip = ZipWriter::new(File::create(&path).unwrap());
+                for (file, data) in *files {
+                    zip.start_file(*file, SimpleFileOptions::default()).unwrap();
+                    zip.write_all(data.as_bytes()).unwrap();
+                }
+                zip.finish().unwrap();
+                PendingArchive {
+                    path,
+                    name,
+                    operations: operations.clone(),
+                }
+            })
+            .collect()
+    }
+
+    fn outcome(transaction: &StagedUpdate) -> Outcome {
+        let staging = staging_dir(&transaction.install_dir);
+        let staged = list_files(&staging)
+            .unwrap()
+            .into_iter()
+            .map(|path| {
+                let data = fs::read(staging.join(&path)).unwrap();
+                (path, data)
+            })
+            .collect();
+        (
+            staged,
+            transaction.removed.clone(),
+            transaction.origins.clone(),
+        )
+    }
+
+    /// Stages the archives over an install holding `installed`, once merged and once one
+    /// archive at a time, and checks both leave the same staged update.
+    fn stage_both_ways(installed: &[(&str, &str)], archives: &[TestArchive]) -> Outcome {
+        let stage = |merged: bool| {
+            let dir = tempfile::tempdir().unwrap();
+            let install_dir = dir.path().join("game");
+            for (path, data) in installed {
+                let file = install_dir.join(path);
+                fs::create_dir_all(file.parent().unwrap()).unwrap();
+                fs::write(file, data).unwrap();
+            }
+            let pending = pending_archives(dir.path(), archives);
+            let mut transaction = StagedUpdate::begin(&install_dir, Some("2.0")).unwrap();
+            if merged {
+                transaction.stage_archives(&pending).unwrap();
+            } else {
+                for archive in &pending {
+                    transaction
+                        .stage_archive(&archive.path, &archive.name)
+                        .unwrap();
+                    stage_operations(&mut transaction, &archive.operations).unwrap();
+                }
+            }
+            let outcome = outcome(&transaction);
+            transaction.abort().unwrap();
+            outcome
+        };
+        let merged = stage(true);
+        assert_eq!(merged, stage(false));
+        merged
+    }
+
+    fn staged(files: &[(&str, &str)]) -> BTreeMap<String, Vec<u8>> {
+        files
+            .iter()
+            .map(|(path, data)| (path.to_string(), data.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn paths(paths: &[&str]) -> BTreeSet<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn merged_staging_keeps_the_newest_copy_of_overwritten_files() {
+        let (files, removed, origins) = stage_both_ways(
+            &[("mod/a.bin", "installed")],
+            &[
+                (&[("mod/a.bin", "first"), ("mod/b.bin", "first")], vec![]),
+                (&[("mod/a.bin", "second")], vec![]),
+            ],
+        );
+        assert_eq!(
+            files,
+            staged(&[("mod/a.bin", "second"), ("mod/b.bin", "first")])
+        );
+        assert!(removed.is_empty());
+        assert_eq!(origins["mod/a.bin"], "update-1.zip");
+        assert_eq!(origins["mod/b.bin"], "update-0.zip");
+    }
+
+    #[test]
+    fn merged_staging_matches_deleting_and_readding_a_file() {
+        let (files, removed, _) = stage_both_ways(
+            &[("mod/a.bin", "installed"), ("mod/c.bin", "installed")],
+            &[
+                (
+                    &[("mod/x.bin", "first")],
+                    vec![
+                        delete("mod/a.bin"),
+                        delete("mod/c.bin"),
+                        delete("mod/x.bin"),
+                    ],
+                ),
+                (&[("mod/a.bin", "readded")], vec![]),
+                (&[("mod/x.bin", "readded")], vec![delete("mod/a.bin")]),
+            ],
+        );
+        assert_eq!(files, staged(&[("mod/x.bin", "readded")]));
+        assert_eq!(removed, paths(&["mod/a.bin", "mod/c.bin"]));
+    }
+
+    #[test]
+    fn merged_staging_matches_renaming_an_installed_file() {
+        let (files, removed, origins) = stage_both_ways(
+            &[("mod/old.bin", "installed"), ("mod/keep.bin", "installed")],
+            &[
+                (&[], vec![rename("mod/old.bin", "mod/new/renamed.bin")]),
+                (
+                    &[("mod/keep.bin", "updated")],
+                    vec![rename("mod/keep.bin", "mod/kept.bin")],
+                ),
+            ],
+        );
+        assert_eq!(
+            files,
+            staged(&[
+                ("mod/kept.bin", "updated"),
+                ("mod/new/renamed.bin", "installed")
+            ])
+        );
+        assert_eq!(removed, paths(&["mod/keep.bin", "mod/old.bin"]));
+        assert!(!origins.contains_key("mod/new/renamed.bin"));
+        assert_eq!(origins["mod/kept.bin"], "update-1.zip");
+    }
+
+    #[test]
+    fn merged_staging_matches_chained_renames() {
+        let (files, removed, origins) = stage_both_ways(
+            &[("mod/i.bin", "installed")],
+            &[
+                (
+                    &[("mod/a.bin", "shipped")],
+                    vec![
+                        rename("mod/a.bin", "mod/b.bin"),
+                        rename("mod/i.bin", "mod/j.bin"),
+                    ],
+                ),
+                (
+                    &[("mod/a.bin", "shipped again")],
+                    vec![
+                        rename("mod/b.bin", "mod/c.bin"),
+                        rename("mod/j.bin", "mod/k.bin"),
+                    ],
+                ),
+                (&[], vec![rename("mod/k.bin", "mod/i.bin")]),
+            ],
+        );
+        assert_eq!(
+            files,
+            staged(&[
+                ("mod/a.bin", "shipped again"),
+                ("mod/c.bin", "shipped"),
+                ("mod/i.bin", "installed")
+            ])
+        );
+        assert!(removed.is_empty());
+        assert_eq!(origins["mod/c.bin"], "update-0.zip");
+        assert_eq!(origins["mod/a.bin"], "update-1.zip");
+    }
+
+    #[test]
+    fn install_relative_path_refuses_the_state_dir_in_any_spelling() {
+        for path in [
+            ".er-downloader/receipt.json",
+            ".ER-Downloader/receipt.json",
+            ".er-downloader./journal.json",
+            ".er-downloader .\\backups/x",
+            "./.Er-DownLoader/vanilla/x",
+        ] {
+            assert!(install_relative_path(path).is_err(), "{}", path);
+        }
+        assert_eq!(
+            install_relative_path("mod\\.er-downloader/x").unwrap(),
+            "mod/.er-downloader/x"
+        );
+    }
+
+    #[test]
+    fn install_relative_path_refuses_escapes() {
+        for path in ["/etc/passwd", "\\share\\x", "mod/../../x", "C:/x", ""] {
+            assert!(install_relative_path(path).is_err(), "{}", path);
+        }
+        assert_eq!(install_relative_path("./mod//a.bin").unwrap(), "mod/a.bin");
+    }
+}
@@ -1,9 +1,28 @@
-mod downloader;
-mod helpers;
+mod cleanup;
+mod cli;
+pub mod core;
+pub mod downloader;
+pub mod events;
+mod frontend;
+pub mod helpers;
+mod install;
+mod migrate;
+mod planner;
+pub mod prompt;
+mod receipt;
+mod settings;
+mod signature;
+mod uninstall;
+mod verify;
+mod version;
+use crate::core::{set_core, Core};
+pub use cli::run_cli;
 use dotenvy_macro::dotenv;
-use downloader::download_updates;
-use helpers::GLOBAL_APP_HANDLE;
-use std::{env, fs::File};
+use downloader::{apply_update_plan, set_download_control, DownloadControl};
+use frontend::{TauriFrontend, GLOBAL_APP_HANDLE};
+use helpers::UpdateStatus;
+use settings::{ConflictPolicy, Settings};
+use std::{env, fs::File, path::Path, sync::Arc};
 use tauri::Emitter;
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 
@@ -43,7 +62,8 @@ use tauri::Emitter;
 
 #[tauri::command]
 async fn extract_file(extract_path: &str) -> Result<(), String> {
-    let file = File::open("Download/Convergence.zip").map_err(|e| e.to_string())?;
+    let file = File::open(crate::core::core().download_dir.join("Convergence.zip"))
+        .map_err(|e| e.to_string())?;
     let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
     println!("Extracting file...");
     println!("Extracting to: {}", extract_path);
@@ -86,17 +106,169 @@ async fn get_patch_notes() -> Result<(), String> {
 
 #[tauri::command]
 async fn check_for_updates(downloading: bool, directory: &str) -> Result<bool, String> {
-    let updates = helpers::check_updates(downloading, directory)
+    install::recover_interrupted_update(Path::new(directory)).map_err(|e| e.to_string())?;
+    let plan = helpers::check_updates(directory)
         .await
         .map_err(|e| e.to_string())?;
 
     if !downloading {
-        Ok(updates.is_empty())
-    } else {
-        download_updates(updates, directory)
-            .await
-            .map_err(|e| e.to_string())
+        if plan.status != UpdateStatus::NotInstalled {
+            let (title, message) = plan.summary();
+            prompt::notify(title, &message);
+        }
+        return Ok(!matches!(
+            plan.status,
+            UpdateStatus::Available | UpdateStatus::Unsupported
+        ));
     }
+    set_download_control(DownloadControl::Running);
+    apply_update_plan(plan, directory)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_update_plan(directory: &str) -> Result<helpers::UpdatePlan, String> {
+    install::recover_interrupted_update(Path::new(directory)).map_err(|e| e.to_string())?;
+    helpers::check_updates(directory)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn pause_download() {
+    println!("Pausing download...");
+    set_download_control(DownloadControl::Paused);
+}
+
+#[tauri::command]
+fn resume_download() {
+    println!("Resuming download...");
+    set_download_control(DownloadControl::Running);
+}
+
+#[tauri::command]
+fn cancel_download(delete_partial: bool) {
+    println!("Cancelling download...");
+    set_download_control(DownloadControl::Cancelled { delete_partial });
+    frontend::cancel_prompts();
+}
+
+#[tauri::command]
+fn answer_prompt(id: u64, answer: bool) -> Result<(), String> {
+    frontend::answer_prompt(id, answer).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rollback_update(directory: &str) -> Result<String, String> {
+    let restored = install::rollback_update(Path::new(directory)).map_err(|e| e.to_string())?;
+    Ok(match restored {
+        Some(version) => format!("Rolled back to {}", version),
+        None => "Removed the installed files".to_string(),
+    })
+}
+
+#[tauri::command]
+async fn verify_installation(directory: &str) -> Result<verify::VerifyReport, String> {
+    verify::verify_installation(Path::new(directory))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn repair_installation(directory: &str) -> Result<verify::VerifyReport, String> {
+    set_download_control(DownloadControl::Running);
+    verify::repair_installation(Path::new(directory))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn preview_uninstall(directory: &str) -> Result<uninstall::UninstallPreview, String> {
+    uninstall::preview_uninstall(Path::new(directory)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn uninstall_mod(directory: &str) -> Result<String, String> {
+    let path = Path::new(directory);
+    let preview = uninstall::preview_uninstall(path).map_err(|e| e.to_string())?;
+    if !uninstall::confirm_uninstall(&preview).await {
+        return Ok("Uninstall cancelled".to_string());
+    }
+    let removed = uninstall::uninstall_mod(path).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "Removed {} file(s) and restored {} original file(s)",
+        removed.remove.len(),
+        removed.restore.len()
+    ))
+}
+
+#[tauri::command]
+async fn find_orphaned_files(directory: &str) -> Result<Vec<String>, String> {
+    cleanup::find_orphaned_files(Path::new(directory))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clean_orphaned_files(
+    directory: &str,
+    action: cleanup::CleanupAction,
+) -> Result<cleanup::CleanupRecord, String> {
+    let install_dir = Path::new(directory);
+    let orphans = cleanup::find_orphaned_files(install_dir)
+        .await
+        .map_err(|e| e.to_string())?;
+    cleanup::clean_orphaned_files(install_dir, &orphans, action).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_versions(directory: &str) -> Result<Vec<helpers::VersionInfo>, String> {
+    helpers::list_versions(directory)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn install_version(directory: &str, version: &str, pin: bool) -> Result<bool, String> {
+    install::recover_interrupted_update(Path::new(directory)).map_err(|e| e.to_string())?;
+    set_download_control(DownloadControl::Running);
+    downloader::install_version(version, pin, directory)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_pinned_version(directory: &str, version: Option<String>) -> Result<(), String> {
+    let install_dir = Path::new(directory);
+    let mut settings = settings::load_settings(install_dir);
+    settings.pinned_version = match version {
+        Some(version) => Some(
+            helpers::published_version(&version, settings.channel)
+                .await
+                .map_err(|e| e.to_string())?,
+        ),
+        None => None,
+    };
+    settings::save_settings(install_dir, &settings).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_settings(directory: &str) -> Settings {
+    settings::load_settings(Path::new(directory))
+}
+
+#[tauri::command]
+fn save_settings(directory: &str, settings: Settings) -> Result<(), String> {
+    settings::save_settings(Path::new(directory), &settings).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn set_conflict_policy(directory: &str, path: &str, policy: ConflictPolicy) -> Result<(), String> {
+    let install_dir = Path::new(directory);
+    let mut settings = settings::load_settings(install_dir);
+    settings.conflict_policies.insert(path.to_string(), policy);
+    settings::save_settings(install_dir, &settings).map_err(|e| e.to_string())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -109,7 +281,25 @@ pub fn run() {
             // download_er,
             extract_file,
             get_patch_notes,
-            check_for_updates
+            check_for_updates,
+            get_update_plan,
+            pause_download,
+            resume_download,
+            cancel_download,
+            answer_prompt,
+            rollback_update,
+            verify_installation,
+            repair_installation,
+            preview_uninstall,
+            uninstall_mod,
+            find_orphaned_files,
+            clean_orphaned_files,
+            list_versions,
+            install_version,
+            set_pinned_version,
+            get_settings,
+            save_settings,
+            set_conflict_policy
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
@@ -121,6 +311,12 @@ pub fn run() {
                         GLOBAL_APP_HANDLE
                             .set(_app_handle.clone())
                             .expect("Failed to set global app handle");
+                        let frontend = Arc::new(TauriFrontend::new(_app_handle.clone()));
+                        set_core(Core {
+                            sink: frontend.clone(),
+                            prompter: frontend,
+                            ..Core::default()
+                        });
                     }
                     _ => {}
                 }
@@ -0,0 +1,196 @@
+use crate::cleanup::fetch_file_list;
+use crate::downloader::{download_full_archive, install_full_archive};
+use crate::helpers::fetch_manifest;
+use crate::install::{forget_install, list_files, move_file, state_dir, vanilla_dir, STATE_DIR};
+use crate::prompt::{confirm, PromptKey};
+use crate::receipt::{hash_file, load_receipt, Receipt};
+use crate::settings::{load_settings, ConflictPolicy};
+use crate::uninstall::remove_installed_files;
+use crate::version::ModVersion;
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Player files held here while the old install is replaced.
+fn migration_dir(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("migration")
+}
+
+/// Edited config files plus anything the player added inside the mod's folders, such as saves.
+fn preserved_files(
+    install_dir: &Path,
+    receipt: &Receipt,
+    old_files: &BTreeSet<String>,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let settings = load_settings(install_dir);
+    let mut preserved = Vec::new();
+    for path in old_files {
+        let file = install_dir.join(path);
+        if settings.conflict_policy(path) == ConflictPolicy::TakeNew || !file.exists() {
+            continue;
+        }
+        // Installs migrated from version.txt have no hashes, so any config may have been edited
+        let edited = match receipt.files.get(path) {
+            Some(installed) => {
+                let (size, sha256) = hash_file(&file)?;
+                size != installed.size || sha256 != installed.sha256
+            }
+            None => true,
+        };
+        if edited {
+            preserved.push(path.clone());
+        }
+    }
+
+    let roots: BTreeSet<&str> = old_files
+        .iter()
+        .filter_map(|path| path.split_once('/').map(|(root, _)| root))
+        .filter(|root| *root != STATE_DIR)
+        .collect();
+    for root in roots {
+        let dir = install_dir.join(root);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in list_files(&dir)? {
+            let path = format!("{}/{}", root, file);
+            if !old_files.contains(&path) {
+                preserved.push(path);
+            }
+        }
+    }
+    Ok(preserved)
+}
+
+fn back_up_files(install_dir: &Path, files: &[String]) -> std::io::Result<()> {
+    let backup_dir = migration_dir(install_dir);
+    for path in files {
+        let backup = backup_dir.join(path);
+        if let Some(parent) = backup.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(install_dir.join(path), backup)?;
+    }
+    Ok(())
+}
+
+/// Puts the backed up files back. Where the fresh install ships a different copy, that
+/// copy is kept next to the player's as `<name>.new`.
+fn restore_files(install_dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let backup_dir = migration_dir(install_dir);
+    if !backup_dir.exists() {
+        return Ok(0);
+    }
+    let shipped = load_receipt(install_dir)?
+        .map(|receipt| receipt.files)
+        .unwrap_or_default();
+    let files = list_files(&backup_dir)?;
+    for path in &files {
+        let backup = backup_dir.join(path);
+        let target = install_dir.join(path);
+        if target.exists()
+            && shipped.contains_key(path)
+            && hash_file(&target)? != hash_file(&backup)?
+        {
+            move_file(&target, &install_dir.join(format!("{}.new", path)))?;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&backup, &target)?;
+    }
+    fs::remove_dir_all(&backup_dir)?;
+    Ok(files.len())
+}
+
+/// Puts back every original game file, including ones replaced by files the receipt no
+/// longer lists, before the vanilla copies are deleted with the rest of the old install.
+fn restore_vanilla_files(install_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let vanilla = vanilla_dir(install_dir);
+    if !vanilla.is_dir() {
+        return Ok(());
+    }
+    for path in list_files(&vanilla)? {
+        move_file(&vanilla.join(&path), &install_dir.join(&path))?;
+    }
+    Ok(())
+}
+
+async fn confirm_migration(current: &ModVersion, latest: &ModVersion) -> bool {
+    confirm(
+        PromptKey::Migrate,
+        "Migrate old version",
+        &format!(
+            "Convergence {} is too old to be updated to {}.\n\
+             The downloader can back up your edited settings and saves, remove {}, \
+             install {} from scratch and put your files back.",
+            current, latest, current, latest
+        ),
+        &format!("Migrate to {}", latest),
+        &format!("Stay on {}", current),
+    )
+    .await
+}
+
+/// Replaces an install older than the manifest's `least` version with a fresh install of
+/// `latest`, keeping the player's settings and saves. If the install fails the files are
+/// still put back; if restoring them fails they stay in `.er-downloader/migration`.
+pub async fn migrate_install(
+    current: &ModVersion,
+    least: &ModVersion,
+    latest: &ModVersion,
+    extract_path: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let install_dir = Path::new(extract_path);
+    println!(
+        "Version {} is below the supported {}, migrating to {}",
+        current, least, latest
+    );
+    if !confirm_migration(current, latest).await {
+        println!("Staying on {}", current);
+        return Ok(false);
+    }
+    let Some(receipt) = load_receipt(install_dir)? else {
+        return Err("No Convergence install was found in this directory".into());
+    };
+
+    let mut old_files: BTreeSet<String> = receipt.files.keys().cloned().collect();
+    // Installs migrated from version.txt only know the files their later updates touched
+    if !receipt.complete {
+        let manifest = fetch_manifest().await?.for_channel(receipt.channel)?;
+        match manifest.file_lists.get(current) {
+            Some(list) => old_files.extend(fetch_file_list(list).await?),
+            None => println!(
+                "The update manifest has no file list for {}, installing over the old files",
+                current
+            ),
+        }
+    }
+
+    // A pinned version is installed as itself, anything else gets the channel's latest.
+    // The archive is fetched before anything is removed so a failed download leaves the
+    // old install playable.
+    let pinned = load_settings(install_dir)
+        .pinned_version
+        .filter(|pin| pin == latest);
+    let (archive_path, archive) = download_full_archive(extract_path, pinned.as_ref()).await?;
+
+    let preserved = preserved_files(install_dir, &receipt, &old_files)?;
+    back_up_files(install_dir, &preserved)?;
+    println!("Backed up {} file(s)", preserved.len());
+    if !old_files.is_empty() {
+        let old_files: Vec<String> = old_files.into_iter().collect();
+        println!("Removing {} file(s) of {}", old_files.len(), current);
+        remove_installed_files(install_dir, &old_files)?;
+        restore_vanilla_files(install_dir)?;
+        forget_install(install_dir)?;
+    }
+
+    let installed =
+        install_full_archive(extract_path, pinned.as_ref(), &archive_path, &archive).await;
+    let restored = restore_files(install_dir)?;
+    println!("Restored {} file(s)", restored);
+    installed
+}
@@ -0,0 +1,284 @@
+use crate::helpers::{format_size, UpdateArchive, UpdateInfo};
+use crate::settings::Channel;
+use crate::version::ModVersion;
+use serde::Serialize;
+
+/// The archives that take an install to a version: optionally a full archive, then
+/// incremental updates on top of it, oldest first.
+#[derive(Serialize, Debug, Clone)]
+pub struct UpdateRoute {
+    pub full: Option<(ModVersion, UpdateArchive)>,
+    pub updates: Vec<(ModVersion, UpdateArchive)>,
+    /// Bytes to download, `None` when the manifest doesn't list every archive's size.
+    pub total_size: Option<u64>,
+}
+
+impl UpdateRoute {
+    fn new(
+        full: Option<(ModVersion, UpdateArchive)>,
+        updates: Vec<(ModVersion, UpdateArchive)>,
+    ) -> Self {
+        let total_size = full
+            .iter()
+            .chain(&updates)
+            .map(|(_, archive)| archive.size)
+            .sum();
+        UpdateRoute {
+            full,
+            updates,
+            total_size,
+        }
+    }
+
+    pub fn target(&self) -> Option<&ModVersion> {
+        self.updates
+            .last()
+            .or(self.full.as_ref())
+            .map(|(version, _)| version)
+    }
+
+    /// Every archive in the order it has to be extracted.
+    pub fn into_archives(self) -> Vec<(ModVersion, UpdateArchive)> {
+        self.full.into_iter().chain(self.updates).collect()
+    }
+
+    /// A one-line summary for dialogs and logs, e.g. "full install of 2.0 + 3 update(s), 1.2 GB".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some((version, _)) = &self.full {
+            parts.push(format!("full install of {}", version));
+        }
+        if !self.updates.is_empty() {
+            parts.push(format!("{} update(s)", self.updates.len()));
+        }
+        let size = match self.total_size {
+            Some(size) => format_size(size),
+            None => "size unknown".to_string(),
+        };
+        format!("{}, {}", parts.join(" + "), size)
+    }
+}
+
+/// Picks the smallest download that takes `current` to `target`: the incremental chain, a
+/// full archive, or a full archive of an older version plus the updates after it. Without
+/// a size for every archive of the chain there is nothing to weigh, so the chain is used.
+pub fn plan_route(
+    update_info: &UpdateInfo,
+    current: Option<&ModVersion>,
+    target: &ModVersion,
+    channel: Channel,
+) -> Option<UpdateRoute> {
+    let chain_to_target = |from: &ModVersion| {
+        let chain = update_info.update_chain(from, target);
+        let reaches = from == target || chain.last().is_some_and(|(version, _)| version == target);
+        reaches.then_some(chain)
+    };
+
+    let incremental = current
+        .filter(|current| *current >= &update_info.least && *current < target)
+        .and_then(chain_to_target)
+        .map(|chain| UpdateRoute::new(None, chain));
+    let mut routes = match incremental {
+        Some(route) if route.total_size.is_none() => return Some(route),
+        Some(route) => vec![route],
+        None => Vec::new(),
+    };
+    for version in update_info.versions() {
+        if version > *target {
+            break;
+        }
+        let Some(full) = update_info.full_archive(&version, channel) else {
+            continue;
+        };
+        if let Some(chain) = chain_to_target(&version) {
+            routes.push(UpdateRoute::new(Some((version, full)), chain));
+        }
+    }
+
+    // Ties go to the incremental chain, then to the full archive with the fewest updates
+    routes.into_iter().min_by_key(|route| {
+        (
+            route.total_size.is_none(),
+            route.total_size,
+            route.full.is_some(),
+            route.updates.len(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn version(s: &str) -> ModVersion {
+        s.parse().unwrap()
+    }
+
+    fn archive(path: &str, size: u64) -> Value {
+        json!({ "path": path, "size": size })
+    }
+
+    /// Updates 1.1 to 1.3 on top of `least` 1.0, with the given update and full archive sizes.
+    fn update_info(update_sizes: [u64; 3], full_archives: Value) -> UpdateInfo {
+        serde_json::from_value(json!({
+            "latest": "1.3",
+            "least": "1.0",
+            "updates": {
+                "1.1": archive("/updates/1.1.zip", update_sizes[0]),
+                "1.2": archive("/updates/1.2.zip", update_sizes[1]),
+                "1.3": archive("/updates/1.3.zip", update_sizes[2]),
+            },
+            "full_archives": full_archives,
+        }))
+        .unwrap()
+    }
+
+    /// The full archive's version, the update versions and the total size of a route.
+    fn plan(
+        update_info: &UpdateInfo,
+        current: Option<&str>,
+        target: &str,
+    ) -> (Option<String>, Vec<String>, Option<u64>) {
+        let current = current.map(version);
+        let route = plan_route(
+            update_info,
+            current.as_ref(),
+            &version(target),
+            Channel::Stable,
+        )
+        .unwrap();
+        (
+            route.full.map(|(version, _)| version.to_string()),
+            route
+                .updates
+                .iter()
+                .map(|(version, _)| version.to_string())
+                .collect(),
+            route.total_size,
+        )
+    }
+
+    #[test]
+    fn prefers_the_incremental_chain_when_it_is_smallest() {
+        let info = update_info(
+            [10, 10, 10],
+            json!({ "1.1": archive("/full/1.1.zip", 50), "1.3": archive("/full/1.3.zip", 100) }),
+        );
+        assert_eq!(
+            plan(&info, Some("1.0"), "1.3"),
+            (
+                None,
+                vec!["1.1".into(), "1.2".into(), "1.3".into()],
+                Some(30)
+            )
+        );
+        assert_eq!(
+            plan(&info, Some("1.1"), "1.2"),
+            (None, vec!["1.2".into()], Some(10))
+        );
+    }
+
+    #[test]
+    fn prefers_a_full_archive_when_it_is_smallest() {
+        let info = update_info(
+            [500, 10, 10],
+            json!({ "1.1": archive("/full/1.1.zip", 80), "1.3": archive("/full/1.3.zip", 60) }),
+        );
+        assert_eq!(
+            plan(&info, Some("1.0"), "1.3"),
+            (Some("1.3".into()), vec![], Some(60))
+        );
+    }
+
+    #[test]
+    fn prefers_an_older_full_archive_plus_updates_when_it_is_smallest() {
+        let info = update_info(
+            [500, 10, 10],
+            json!({ "1.1": archive("/full/1.1.zip", 50), "1.3": archive("/full/1.3.zip", 100) }),
+        );
+        assert_eq!(
+            plan(&info, Some("1.0"), "1.3"),
+            (
+                Some("1.1".into()),
+                vec!["1.2".into(), "1.3".into()],
+                Some(70)
+            )
+        );
+        // A fresh install or a downgrade has no incremental chain to weigh
+        assert_eq!(
+            plan(&info, None, "1.2"),
+            (Some("1.1".into()), vec!["1.2".into()], Some(60))
+        );
+        assert_eq!(
+            plan(&info, Some("1.3"), "1.2"),
+            (Some("1.1".into()), vec!["1.2".into()], Some(60))
+        );
+    }
+
+    #[test]
+    fn ties_go_to_the_incremental_chain() {
+        let info = update_info(
+            [10, 10, 10],
+            json!({ "1.1": archive("/full/1.1.zip", 10), "1.3": archive("/full/1.3.zip", 30) }),
+        );
+        assert_eq!(
+            plan(&info, Some("1.0"), "1.3"),
+            (
+                None,
+                vec!["1.1".into(), "1.2".into(), "1.3".into()],
+                Some(30)
+            )
+        );
+    }
+
+    #[test]
+    fn ties_between_full_archives_go_to_fewer_updates() {
+        let info = update_info(
+            [10, 10, 10],
+            json!({ "1.1": archive("/full/1.1.zip", 20), "1.3": archive("/full/1.3.zip", 40) }),
+        );
+        assert_eq!(
+            plan(&info, None, "1.3"),
+            (Some("1.3".into()), vec![], Some(40))
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_incremental_chain_when_its_size_is_unknown() {
+        let mut info = update_info([10, 10, 10], json!({ "1.3": archive("/full/1.3.zip", 1) }));
+        info.updates
+            .insert(version("1.2"), UpdateArchive::from_path("/updates/1.2.zip"));
+        assert_eq!(
+            plan(&info, Some("1.0"), "1.3"),
+            (None, vec!["1.1".into(), "1.2".into(), "1.3".into()], None)
+        );
+    }
+
+    #[test]
+    fn prefers_sized_routes_over_unsized_full_archives() {
+        // The latest release always has the channel's full archive, but without a size
+        let info = update_info([10, 10, 10], json!({ "1.1": archive("/full/1.1.zip", 50) }));
+        assert_eq!(
+            plan(&info, None, "1.3"),
+            (
+                Some("1.1".into()),
+                vec!["1.2".into(), "1.3".into()],
+                Some(70)
+            )
+        );
+
+        let info = update_info([10, 10, 10], json!({}));
+        let (full, updates, size) = plan(&info, Some("0.9"), "1.3");
+        assert_eq!(
+            (full.as_deref(), updates.len(), size),
+            (Some("1.3"), 0, None)
+        );
+    }
+
+    #[test]
+    fn no_route_without_a_full_archive_or_chain() {
+        let info = update_info([10, 10, 10], json!({}));
+        assert!(plan_route(&info, None, &version("1.2"), Channel::Stable).is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::core::core;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, future::Future, pin::Pin};
+
+/// The questions the backend can ask, so headless runs can be given a fixed answer to each.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptKey {
+    /// Install the full mod into a directory without an install.
+    FullInstall,
+    /// Continue an update that touches files the player edited.
+    Conflicts,
+    /// Downgrade an install that is newer than the latest release.
+    Downgrade,
+    SwitchChannel,
+    /// Replace an install older than the least supported version.
+    Migrate,
+    /// Clean up files older versions left behind.
+    OrphanCleanup,
+    /// Quarantine the leftover files rather than deleting them.
+    OrphanQuarantine,
+    Uninstall,
+}
+
+impl PromptKey {
+    /// The answer [`HeadlessPrompter`] gives unless told otherwise. Anything that replaces
+    /// or removes the install is declined, since nobody saw what it would do.
+    pub fn default_answer(self) -> bool {
+        match self {
+            PromptKey::FullInstall
+            | PromptKey::Downgrade
+            | PromptKey::SwitchChannel
+            | PromptKey::Migrate
+            | PromptKey::OrphanCleanup
+            | PromptKey::Uninstall => false,
+            PromptKey::Conflicts | PromptKey::OrphanQuarantine => true,
+        }
+    }
+}
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A yes/no question; `ok` is the label for yes, `cancel` for no.
+#[derive(Serialize, Debug, Clone)]
+pub struct Confirmation {
+    pub key: PromptKey,
+    pub title: String,
+    pub message: String,
+    pub ok: String,
+    pub cancel: String,
+}
+
+/// Asks the player things on behalf of the update logic.
+pub trait Prompter: Send + Sync {
+    /// Shows a message without waiting for the player.
+    fn notify(&self, title: &str, message: &str);
+
+    fn confirm(&self, confirmation: Confirmation) -> BoxFuture<'_, bool>;
+}
+
+/// Answers every question with a fixed answer per [`PromptKey`].
+pub struct HeadlessPrompter {
+    answers: HashMap<PromptKey, bool>,
+}
+
+impl HeadlessPrompter {
+    pub fn new(answers: HashMap<PromptKey, bool>) -> Self {
+        HeadlessPrompter { answers }
+    }
+
+    /// Reads answers like `ER_DOWNLOADER_ANSWERS=migrate=no,orphan_cleanup=yes`.
+    pub fn from_env() -> Self {
+        let Ok(answers) = std::env::var("ER_DOWNLOADER_ANSWERS") else {
+            return HeadlessPrompter::new(HashMap::new());
+        };
+        let answers = answers
+            .split(',')
+            .filter_map(|pair| {
+                let (key, answer) = pair.split_once('=')?;
+                let key = serde_json::from_value(serde_json::Value::from(key.trim())).ok();
+                let answer = match answer.trim() {
+                    "yes" | "true" => Some(true),
+                    "no" | "false" => Some(false),
+                    _ => None,
+                };
+                if key.is_none() || answer.is_none() {
+                    eprintln!("Ignoring invalid prompt answer \"{}\"", pair);
+                }
+                Some((key?, answer?))
+            })
+            .collect();
+        HeadlessPrompter::new(answers)
+    }
+
+    pub fn answer(&self, key: PromptKey) -> bool {
+        self.answers
+            .get(&key)
+            .copied()
+            .unwrap_or_else(|| key.default_answer())
+    }
+}
+
+impl Prompter for HeadlessPrompter {
+    fn notify(&self, title: &str, message: &str) {
+        println!("{}: {}", title, message);
+    }
+
+    fn confirm(&self, confirmation: Confirmation) -> BoxFuture<'_, bool> {
+        let answer = self.answer(confirmation.key);
+        println!(
+            "{}: {} -> {}",
+            confirmation.title,
+            confirmation.message,
+            if answer {
+                &confirmation.ok
+            } else {
+                &confirmation.cancel
+            }
+        );
+        Box::pin(async move { answer })
+    }
+}
+
+pub fn notify(title: &str, message: &str) {
+    core().prompter.notify(title, message);
+}
+
+pub async fn confirm(key: PromptKey, title: &str, message: &str, ok: &str, cancel: &str) -> bool {
+    let prompter = core().prompter.clone();
+    prompter
+        .confirm(Confirmation {
+            key,
+            title: title.to_string(),
+            message: message.to_string(),
+            ok: ok.to_string(),
+            cancel: cancel.to_string(),
+        })
+        .await
+}
@@ -0,0 +1,101 @@
+use crate::install::{read_version_file, state_dir, write_atomic};
+use crate::settings::Channel;
+use crate::version::{ModVersion, VersionError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Everything the downloader knows about an install: the version and every file it wrote.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Receipt {
+    pub version: String,
+    /// Channel the installed version came from.
+    #[serde(default)]
+    pub channel: Channel,
+    pub installed_at: u64,
+    pub updated_at: u64,
+    pub files: BTreeMap<String, ReceiptFile>,
+    /// `files` lists the whole install. Only a full install sets this; an install migrated
+    /// from version.txt only knows the files its later updates touched.
+    #[serde(default)]
+    pub complete: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptFile {
+    pub size: u64,
+    pub sha256: String,
+    /// Manifest path of the archive the file was extracted from.
+    pub archive: String,
+    pub installed_at: u64,
+}
+
+impl Receipt {
+    pub fn installed_version(&self) -> Result<ModVersion, VersionError> {
+        self.version.parse()
+    }
+
+    pub fn new(version: &str) -> Self {
+        let now = unix_time();
+        Receipt {
+            version: version.to_string(),
+            channel: Channel::Stable,
+            installed_at: now,
+            updated_at: now,
+            files: BTreeMap::new(),
+            complete: false,
+        }
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn receipt_path(install_dir: &Path) -> PathBuf {
+    state_dir(install_dir).join("receipt.json")
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)?;
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+/// Loads the install receipt, migrating a legacy `version.txt` into one if needed.
+pub fn load_receipt(install_dir: &Path) -> Result<Option<Receipt>, Box<dyn std::error::Error>> {
+    let path = receipt_path(install_dir);
+    if path.exists() {
+        return Ok(Some(serde_json::from_slice(&fs::read(path)?)?));
+    }
+
+    let Some(version) = read_version_file(install_dir) else {
+        return Ok(None);
+    };
+    println!("Migrating version.txt ({}) to an install receipt", version);
+    let receipt = Receipt::new(&version);
+    save_receipt(install_dir, &receipt)?;
+    fs::remove_file(install_dir.join("version.txt"))?;
+    Ok(Some(receipt))
+}
+
+pub fn save_receipt(
+    install_dir: &Path,
+    receipt: &Receipt,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(state_dir(install_dir))?;
+    write_atomic(
+        &receipt_path(install_dir),
+        &serde_json::to_vec_pretty(receipt)?,
+    )?;
+    Ok(())
+}
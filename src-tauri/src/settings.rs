@@ -0,0 +1,109 @@
+use crate::install::{write_atomic, STATE_DIR};
+use crate::version::ModVersion;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Per-install preferences, stored alongside the rest of the downloader's bookkeeping.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// How many pre-update snapshots to keep for `rollback_update`.
+    pub backup_retention: usize,
+    /// What to do when an update touches a file the player edited, keyed by install-relative path.
+    pub conflict_policies: BTreeMap<String, ConflictPolicy>,
+    /// Which release channel updates come from.
+    pub channel: Channel,
+    /// Stay on this version; newer releases aren't offered until the pin is removed.
+    pub pinned_version: Option<ModVersion>,
+}
+
+/// Release channels, ordered from most to least stable.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Stable,
+    Beta,
+    Nightly,
+}
+
+impl Channel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+        }
+    }
+
+    /// Dropbox path of the channel's full install archive.
+    pub fn full_archive(self) -> &'static str {
+        match self {
+            Channel::Stable => "/ConvergenceER.zip",
+            Channel::Beta => "/beta/ConvergenceER.zip",
+            Channel::Nightly => "/nightly/ConvergenceER.zip",
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            backup_retention: 3,
+            conflict_policies: BTreeMap::new(),
+            channel: Channel::Stable,
+            pinned_version: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Leave the player's copy alone and skip the new file.
+    Keep,
+    /// Overwrite the player's copy with the new file.
+    TakeNew,
+    /// Leave the player's copy alone and write the new file next to it as `<name>.new`.
+    SaveAsNew,
+}
+
+impl Settings {
+    /// Config files default to keeping the player's edits; everything else gets replaced.
+    pub fn conflict_policy(&self, path: &str) -> ConflictPolicy {
+        if let Some(policy) = self.conflict_policies.get(path) {
+            return *policy;
+        }
+        let extension = Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("toml" | "ini" | "cfg" | "json" | "yaml" | "yml" | "xml") => {
+                ConflictPolicy::SaveAsNew
+            }
+            _ => ConflictPolicy::TakeNew,
+        }
+    }
+}
+
+pub fn load_settings(install_dir: &Path) -> Settings {
+    fs::read(install_dir.join(STATE_DIR).join("settings.json"))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_settings(
+    install_dir: &Path,
+    settings: &Settings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state_dir = install_dir.join(STATE_DIR);
+    fs::create_dir_all(&state_dir)?;
+    write_atomic(
+        &state_dir.join("settings.json"),
+        &serde_json::to_vec_pretty(settings)?,
+    )?;
+    Ok(())
+}
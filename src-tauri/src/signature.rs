@@ -0,0 +1,48 @@
+use crate::core::core;
+use minisign_verify::{PublicKey, Signature};
+use std::{fs::File, io::Read, path::Path};
+
+fn public_key() -> Result<PublicKey, Box<dyn std::error::Error>> {
+    PublicKey::from_base64(&core().endpoints.public_key)
+        .map_err(|e| format!("Update signing key is invalid: {}", e).into())
+}
+
+/// Checks a minisign signature (the full contents of a `.minisig` file) over `data`.
+pub fn verify_signature(data: &[u8], signature: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let signature =
+        Signature::decode(signature).map_err(|e| format!("Malformed signature: {}", e))?;
+    public_key()?
+        .verify(data, &signature, false)
+        .map_err(|e| format!("Signature verification failed: {}", e).into())
+}
+
+/// Same as [`verify_signature`], but streams the file so large archives are never held in memory.
+pub async fn verify_file_signature(
+    path: &Path,
+    signature: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = path.to_path_buf();
+    let signature = signature.to_string();
+    tokio::task::spawn_blocking(move || -> Result<(), String> {
+        let signature =
+            Signature::decode(&signature).map_err(|e| format!("Malformed signature: {}", e))?;
+        let public_key = public_key().map_err(|e| e.to_string())?;
+        let mut verifier = public_key
+            .verify_stream(&signature)
+            .map_err(|e| format!("Signature verification failed: {}", e))?;
+        let mut file = File::open(path).map_err(|e| e.to_string())?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+            if read == 0 {
+                break;
+            }
+            verifier.update(&buffer[..read]);
+        }
+        verifier
+            .finalize()
+            .map_err(|e| format!("Signature verification failed: {}", e))
+    })
+    .await?
+    .map_err(|e| e.into())
+}
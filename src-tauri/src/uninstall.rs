@@ -0,0 +1,113 @@
+use crate::install::{
+    list_files, move_file, recover_interrupted_update, remove_empty_parents, state_dir, vanilla_dir,
+};
+use crate::prompt::{confirm, PromptKey};
+use crate::receipt::load_receipt;
+use serde::Serialize;
+use std::{fs, path::Path};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UninstallPreview {
+    pub version: String,
+    /// Files the downloader installed, removed by the uninstall.
+    pub remove: Vec<String>,
+    /// Original game files put back in place of the removed mod files.
+    pub restore: Vec<String>,
+}
+
+pub fn preview_uninstall(
+    install_dir: &Path,
+) -> Result<UninstallPreview, Box<dyn std::error::Error>> {
+    recover_interrupted_update(install_dir)?;
+    let Some(receipt) = load_receipt(install_dir)? else {
+        return Err("No Convergence install was found in this directory".into());
+    };
+    if !receipt.complete {
+        return Err(format!(
+            "The install of {} predates file tracking, so its files can't be removed safely. Update or reinstall it once first.",
+            receipt.version
+        )
+        .into());
+    }
+    // Also covers game files an update deleted, which are no longer in the receipt
+    let vanilla = vanilla_dir(install_dir);
+    let restore = if vanilla.is_dir() {
+        list_files(&vanilla)?
+    } else {
+        Vec::new()
+    };
+    Ok(UninstallPreview {
+        version: receipt.version,
+        remove: receipt.files.into_keys().collect(),
+        restore,
+    })
+}
+
+pub async fn confirm_uninstall(preview: &UninstallPreview) -> bool {
+    const SHOWN: usize = 10;
+    let mut message = format!(
+        "This removes Convergence {} ({} file(s)) and restores {} original game file(s).\n",
+        preview.version,
+        preview.remove.len(),
+        preview.restore.len()
+    );
+    for path in preview.remove.iter().take(SHOWN) {
+        message.push_str(&format!("\n{}", path));
+    }
+    if preview.remove.len() > SHOWN {
+        message.push_str(&format!("\n...and {} more", preview.remove.len() - SHOWN));
+    }
+    confirm(
+        PromptKey::Uninstall,
+        "Uninstall Convergence",
+        &message,
+        "Ok",
+        "Cancel",
+    )
+    .await
+}
+
+/// Removes `files` and puts back the original game files they replaced.
+pub fn remove_installed_files(install_dir: &Path, files: &[String]) -> std::io::Result<()> {
+    let vanilla = vanilla_dir(install_dir);
+    for path in files {
+        let target = install_dir.join(path);
+        if target.exists() {
+            fs::remove_file(&target)?;
+        }
+        let original = vanilla.join(path);
+        if original.exists() {
+            move_file(&original, &target)?;
+        } else {
+            remove_empty_parents(install_dir, &target);
+        }
+    }
+    Ok(())
+}
+
+/// Removes every file the downloader installed, puts the original game files back and
+/// deletes the downloader's bookkeeping, including the install receipt.
+pub fn uninstall_mod(install_dir: &Path) -> Result<UninstallPreview, Box<dyn std::error::Error>> {
+    let preview = preview_uninstall(install_dir)?;
+    println!(
+        "Uninstalling {}: removing {} file(s), restoring {}",
+        preview.version,
+        preview.remove.len(),
+        preview.restore.len()
+    );
+    remove_installed_files(install_dir, &preview.remove)?;
+    let vanilla = vanilla_dir(install_dir);
+    for path in &preview.restore {
+        let original = vanilla.join(path);
+        if original.exists() {
+            move_file(&original, &install_dir.join(path))?;
+        }
+    }
+    let version_file = install_dir.join("version.txt");
+    if version_file.exists() {
+        fs::remove_file(version_file)?;
+    }
+    fs::remove_dir_all(state_dir(install_dir))?;
+    println!("Uninstalled {}", preview.version);
+    Ok(preview)
+}
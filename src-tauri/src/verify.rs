@@ -0,0 +1,211 @@
+use crate::core::core;
+use crate::downloader::download_archive;
+use crate::install::{list_files, run_blocking, StagedUpdate, STATE_DIR};
+use crate::receipt::{hash_file, load_receipt, Receipt};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub version: String,
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub modified: Vec<String>,
+    /// Files inside the mod's folders that no archive installed.
+    pub extra: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.modified.is_empty()
+    }
+}
+
+fn load_tracked_receipt(install_dir: &Path) -> Result<Receipt, Box<dyn std::error::Error>> {
+    let Some(receipt) = load_receipt(install_dir)? else {
+        return Err("No Convergence install was found in this directory".into());
+    };
+    if receipt.files.is_empty() {
+        return Err(format!(
+            "The install of {} predates file tracking. Update or reinstall it once to enable verification.",
+            receipt.version
+        )
+        .into());
+    }
+    Ok(receipt)
+}
+
+/// Hashes every file of the receipt off the async runtime.
+async fn verify_receipt(
+    install_dir: &Path,
+    receipt: &Receipt,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let install_dir = install_dir.to_path_buf();
+    let receipt = receipt.clone();
+    tokio::task::spawn_blocking(move || {
+        hash_receipt_files(&install_dir, &receipt).map_err(|e| e.to_string())
+    })
+    .await?
+    .map_err(|e| e.into())
+}
+
+fn hash_receipt_files(
+    install_dir: &Path,
+    receipt: &Receipt,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let mut report = VerifyReport {
+        version: receipt.version.clone(),
+        checked: receipt.files.len(),
+        ..Default::default()
+    };
+    for (path, expected) in &receipt.files {
+        let file = install_dir.join(path);
+        if !file.exists() {
+            report.missing.push(path.clone());
+            continue;
+        }
+        if fs::metadata(&file)?.len() != expected.size {
+            report.modified.push(path.clone());
+            continue;
+        }
+        let (_, sha256) = hash_file(&file)?;
+        if sha256 != expected.sha256 {
+            report.modified.push(path.clone());
+        }
+    }
+
+    // Without a complete receipt every untracked mod file would look like an extra
+    if !receipt.complete {
+        return Ok(report);
+    }
+    // Only look inside folders the mod installed; the rest of the directory belongs to the game
+    let owned: BTreeSet<&str> = receipt
+        .files
+        .keys()
+        .filter_map(|path| path.split_once('/').map(|(root, _)| root))
+        .filter(|root| *root != STATE_DIR)
+        .collect();
+    for root in owned {
+        let dir = install_dir.join(root);
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in list_files(&dir)? {
+            let path = format!("{}/{}", root, file);
+            if !receipt.files.contains_key(&path) {
+                report.extra.push(path);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Compares the install against the hashes recorded in its receipt.
+pub async fn verify_installation(
+    install_dir: &Path,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let receipt = load_tracked_receipt(install_dir)?;
+    println!(
+        "Verifying {} file(s) of {}",
+        receipt.files.len(),
+        receipt.version
+    );
+    let report = verify_receipt(install_dir, &receipt).await?;
+    println!(
+        "Verification finished: {} missing, {} modified, {} extra",
+        report.missing.len(),
+        report.modified.len(),
+        report.extra.len()
+    );
+    Ok(report)
+}
+
+/// Re-downloads the archives that installed missing or modified files and restores
+/// just those files. Returns the verification report of the repaired install.
+pub async fn repair_installation(
+    install_dir: &Path,
+) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let receipt = load_tracked_receipt(install_dir)?;
+    let report = verify_receipt(install_dir, &receipt).await?;
+    if report.is_ok() {
+        println!("Nothing to repair");
+        return Ok(report);
+    }
+
+    let mut by_archive: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for path in report.missing.iter().chain(&report.modified) {
+        let archive = receipt.files[path].archive.as_str();
+        if archive.is_empty() {
+            return Err(format!("Don't know which archive installed {}", path).into());
+        }
+        by_archive.entry(archive).or_default().insert(path.clone());
+    }
+
+    let mut transaction = StagedUpdate::begin(install_dir, Some(&receipt.version))?;
+    if let Err(e) = stage_repairs(&mut transaction, &receipt, &by_archive).await {
+        transaction.abort()?;
+        return Err(e);
+    }
+    run_blocking(move || transaction.commit_repair()).await?;
+
+    let repaired = verify_receipt(install_dir, &receipt).await?;
+    println!(
+        "Repair finished: {} missing, {} modified",
+        repaired.missing.len(),
+        repaired.modified.len()
+    );
+    Ok(repaired)
+}
+
+async fn stage_repairs(
+    transaction: &mut StagedUpdate,
+    receipt: &Receipt,
+    by_archive: &BTreeMap<&str, BTreeSet<String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for (archive, wanted) in by_archive {
+        println!("Restoring {} file(s) from {}", wanted.len(), archive);
+        let output_path = core().download_path(archive);
+        download_archive(archive, &output_path).await?;
+        let archive = archive.to_string();
+        let wanted: BTreeMap<String, String> = wanted
+            .iter()
+            .map(|path| (path.clone(), receipt.files[path].sha256.clone()))
+            .collect();
+        transaction
+            .run_step(move |transaction| stage_repair(transaction, &output_path, &archive, &wanted))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Extracts the `wanted` files, keyed to their installed hash, and checks that the
+/// archive's copies match the installed version.
+fn stage_repair(
+    transaction: &mut StagedUpdate,
+    output_path: &Path,
+    archive: &str,
+    wanted: &BTreeMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let paths: BTreeSet<String> = wanted.keys().cloned().collect();
+    let staged = transaction.stage_files(output_path, archive, &paths);
+    fs::remove_file(output_path)?;
+    let staged = staged?;
+    for (path, installed_sha256) in wanted {
+        if !staged.contains(path) {
+            return Err(format!("{} no longer contains {}", archive, path).into());
+        }
+        let (_, sha256) = hash_file(&transaction.staged_path(path))?;
+        if sha256 != *installed_sha256 {
+            return Err(format!(
+                "The copy of {} in {} doesn't match the installed version",
+                path, archive
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
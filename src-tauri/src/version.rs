@@ -0,0 +1,231 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// A Convergence release version such as `v2.1`, `2.1.0` or `2.1.0-beta.3+build.7`.
+///
+/// Versions compare like semver: missing numeric components count as zero, a
+/// prerelease sorts before its release, and build metadata is ignored.
+#[derive(Debug, Clone)]
+pub struct ModVersion {
+    raw: String,
+    numbers: Vec<u64>,
+    prerelease: Vec<Identifier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    // Numeric identifiers sort before alphanumeric ones
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+#[derive(Debug)]
+pub struct VersionError(String);
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid version \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for VersionError {}
+
+impl FromStr for ModVersion {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || VersionError(s.to_string());
+        let raw = s.trim();
+        let unprefixed = raw.strip_prefix(['v', 'V']).unwrap_or(raw);
+        let without_build = match unprefixed.split_once('+') {
+            Some((version, build)) => {
+                let valid_build = !build.is_empty()
+                    && build
+                        .split('.')
+                        .all(|part| !part.is_empty() && part.chars().all(is_identifier_char));
+                if !valid_build {
+                    return Err(invalid());
+                }
+                version
+            }
+            None => unprefixed,
+        };
+        let (core, prerelease) = match without_build.split_once('-') {
+            Some((core, prerelease)) => (core, Some(prerelease)),
+            None => (without_build, None),
+        };
+
+        let numbers = core
+            .split('.')
+            .map(|part| {
+                if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(invalid());
+                }
+                part.parse::<u64>().map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let prerelease = match prerelease {
+            None => Vec::new(),
+            Some(prerelease) => prerelease
+                .split('.')
+                .map(|part| {
+                    if part.is_empty() || !part.chars().all(is_identifier_char) {
+                        return Err(invalid());
+                    }
+                    if part.chars().all(|c| c.is_ascii_digit()) {
+                        part.parse().map(Identifier::Numeric).map_err(|_| invalid())
+                    } else {
+                        Ok(Identifier::Alphanumeric(part.to_string()))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        Ok(ModVersion {
+            raw: raw.to_string(),
+            numbers,
+            prerelease,
+        })
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+impl Ord for ModVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        for i in 0..len {
+            let a = self.numbers.get(i).copied().unwrap_or(0);
+            let b = other.numbers.get(i).copied().unwrap_or(0);
+            match a.cmp(&b) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        match (self.prerelease.is_empty(), other.prerelease.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => self.prerelease.cmp(&other.prerelease),
+        }
+    }
+}
+
+impl PartialOrd for ModVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ModVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ModVersion {}
+
+/// Shows the version the way it was written in the manifest.
+impl fmt::Display for ModVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+impl Serialize for ModVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for ModVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> ModVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefix_is_optional_and_kept_for_display() {
+        assert_eq!(version("v2.1.0"), version("2.1.0"));
+        assert_eq!(version("V2.1.0"), version("2.1.0"));
+        assert_eq!(version("v2.1.0").to_string(), "v2.1.0");
+    }
+
+    #[test]
+    fn missing_components_count_as_zero() {
+        assert_eq!(version("2.1"), version("2.1.0"));
+        assert_eq!(version("2"), version("2.0.0.0"));
+        assert!(version("2.1") < version("2.1.1"));
+        assert!(version("2.10") > version("2.9.9"));
+    }
+
+    #[test]
+    fn prereleases_sort_before_their_release() {
+        let ordered = [
+            "2.0.9",
+            "2.1.0-alpha",
+            "2.1.0-beta",
+            "2.1.0-beta.3",
+            "2.1.0-beta.10",
+            "2.1.0-beta.alpha",
+            "2.1.0-rc.1",
+            "2.1.0",
+            "2.1.1-beta.1",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                version(pair[0]) < version(pair[1]),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn build_metadata_is_ignored() {
+        assert_eq!(version("2.1.0+build.7"), version("2.1.0"));
+        assert_eq!(version("2.1.0-beta.3+20240101"), version("2.1.0-beta.3"));
+        assert_eq!(version("v2.1+exp-sha.5114f85"), version("2.1.0+other"));
+    }
+
+    #[test]
+    fn invalid_versions_are_errors() {
+        for s in [
+            "",
+            "v",
+            "v1.x",
+            "1..2",
+            "1.",
+            ".1",
+            "1.0-",
+            "1.0+",
+            "1.0-beta..1",
+            "1.0+build.",
+            "1.0-beta_1",
+            "-1.0",
+            "1.0.0.99999999999999999999",
+        ] {
+            assert!(s.parse::<ModVersion>().is_err(), "{:?} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn deserializes_from_a_string() {
+        let parsed: ModVersion = serde_json::from_str("\"v1.2-beta.1\"").unwrap();
+        assert_eq!(parsed, version("1.2.0-beta.1"));
+        assert!(serde_json::from_str::<ModVersion>("\"1..2\"").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Update flows run end to end against a local server standing in for Dropbox.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use blake2::Blake2b512;
+use ed25519_dalek::{Signer, SigningKey};
+use er_downloader_lib::core::{set_core, Core, Endpoints};
+use er_downloader_lib::downloader::apply_update_plan;
+use er_downloader_lib::events::{Progress, ProgressSink, UpdateRoute};
+use er_downloader_lib::helpers::{check_updates, UpdatePlan, UpdateStatus};
+use er_downloader_lib::prompt::{HeadlessPrompter, PromptKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Cursor, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// Signs files the way `minisign -S` does, with a throwaway key.
+struct MinisignKey {
+    key: SigningKey,
+    key_id: [u8; 8],
+}
+
+impl MinisignKey {
+    fn new() -> Self {
+        MinisignKey {
+            key: SigningKey::from_bytes(&[7; 32]),
+            key_id: *b"testkey!",
+        }
+    }
+
+    fn public_key(&self) -> String {
+        let mut bytes = b"Ed".to_vec();
+        bytes.extend_from_slice(&self.key_id);
+        bytes.extend_from_slice(self.key.verifying_key().as_bytes());
+        STANDARD.encode(bytes)
+    }
+
+    fn sign(&self, data: &[u8]) -> String {
+        let signature = self.key.sign(&Blake2b512::digest(data)).to_bytes();
+        let trusted_comment = "timestamp:0\tfile:test";
+        let mut signed_comment = signature.to_vec();
+        signed_comment.extend_from_slice(trusted_comment.as_bytes());
+        let global_signature = self.key.sign(&signed_comment).to_bytes();
+
+        let mut encoded = b"ED".to_vec();
+        encoded.extend_from_slice(&self.key_id);
+        encoded.extend_from_slice(&signature);
+        format!(
+            "untrusted comment: test\n{}\ntrusted comment: {}\n{}\n",
+            STANDARD.encode(encoded),
+            trusted_comment,
+            STANDARD.encode(global_signature)
+        )
+    }
+}
+
+/// Serves published files by their Dropbox path and answers `files/get_temporary_link`
+/// under `/2` with a link back to itself.
+struct TestServer {
+    url: String,
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl TestServer {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let files = Arc::new(Mutex::new(HashMap::new()));
+        let server = TestServer {
+            url: url.clone(),
+            files: files.clone(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let files = files.clone();
+                let url = url.clone();
+                thread::spawn(move || handle_request(stream, &url, &files));
+            }
+        });
+        server
+    }
+
+    fn publish(&self, path: &str, data: Vec<u8>) {
+        self.files.lock().unwrap().insert(path.to_string(), data);
+    }
+
+    /// Publishes a signed manifest; `update` is the archive for `latest`, if any.
+    fn publish_manifest(&self, key: &MinisignKey, latest: &str, update: Option<&[u8]>) {
+        let mut updates = serde_json::Map::new();
+        if let Some(update) = update {
+            let path = format!("/updates/{}.zip", latest);
+            updates.insert(
+                latest.to_string(),
+                json!({
+                    "path": path,
+                    "sha256": format!("{:x}", Sha256::digest(update)),
+                    "size": update.len(),
+                    "signature": key.sign(update),
+                }),
+            );
+            self.publish(&path, update.to_vec());
+        }
+        let manifest = json!({ "latest": latest, "least": "v1.0", "updates": updates });
+        let manifest = serde_json::to_vec(&manifest).unwrap();
+        self.publish("/manifest.json.minisig", key.sign(&manifest).into_bytes());
+        self.publish("/manifest.json", manifest);
+    }
+}
+
+fn handle_request(mut stream: TcpStream, url: &str, files: &Mutex<HashMap<String, Vec<u8>>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut request = request_line.split_whitespace();
+    let method = request.next().unwrap_or_default().to_string();
+    let path = request.next().unwrap_or_default().to_string();
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+    }
+    let length = headers
+        .get("content-length")
+        .map_or(0, |length| length.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    let files = files.lock().unwrap();
+    if method == "POST" && path == "/2/files/get_temporary_link" {
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let path = request["path"].as_str().unwrap();
+        let Some(data) = files.get(path) else {
+            let error = br#"{"error_summary": "path/not_found/"}"#;
+            return respond(&mut stream, "409 Conflict", &[], error);
+        };
+        let name = path.rsplit_once('/').unwrap().1;
+        let response = json!({
+            "link": format!("{}{}", url, path),
+            "metadata": {
+                "name": name,
+                "path_lower": path.to_lowercase(),
+                "path_display": path,
+                "id": format!("id:{}", name),
+                "client_modified": "2025-01-01T00:00:00Z",
+                "server_modified": "2025-01-01T00:00:00Z",
+                "rev": "1",
+                "size": data.len(),
+                "is_downloadable": true,
+                "content_hash": content_hash(data),
+            },
+        });
+        let response = serde_json::to_vec(&response).unwrap();
+        return respond(&mut stream, "200 OK", &[], &response);
+    }
+
+    let Some(data) = files.get(&path) else {
+        return respond(&mut stream, "404 Not Found", &[], b"");
+    };
+    let range = headers.get("range").and_then(|range| {
+        let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+        let start: usize = start.parse().ok()?;
+        let end: usize = end.parse().unwrap_or(data.len() - 1);
+        Some((start, end.min(data.len() - 1)))
+    });
+    let body = if method == "HEAD" { &[][..] } else { &data[..] };
+    match range {
+        Some((start, end)) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, data.len());
+            let body = if body.is_empty() {
+                body
+            } else {
+                &body[start..=end]
+            };
+            respond(
+                &mut stream,
+                "206 Partial Content",
+                &[("Content-Range", &content_range)],
+                body,
+            );
+        }
+        None if method == "HEAD" => {
+            let length = data.len().to_string();
+            write_response(&mut stream, "200 OK", &[], &length, body);
+        }
+        None => respond(&mut stream, "200 OK", &[], body),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, headers: &[(&str, &str)], body: &[u8]) {
+    write_response(stream, status, headers, &body.len().to_string(), body);
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    content_length: &str,
+    body: &[u8],
+) {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n",
+        status, content_length
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    // The client hangs up early when it only wanted the headers
+    let _ = stream.write_all(response.as_bytes());
+    let _ = stream.write_all(body);
+}
+
+/// Dropbox's `content_hash`: the SHA-256 of the concatenated SHA-256 digests of every 4 MB block.
+fn content_hash(data: &[u8]) -> String {
+    let mut overall = Sha256::new();
+    for block in data.chunks(4 * 1024 * 1024) {
+        overall.update(Sha256::digest(block));
+    }
+    format!("{:x}", overall.finalize())
+}
+
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, data) in files {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[derive(Default)]
+struct RecordingSink {
+    progress: Mutex<Vec<String>>,
+    routes: Mutex<Vec<String>>,
+}
+
+impl ProgressSink for RecordingSink {
+    fn progress(&self, progress: &Progress) {
+        self.progress.lock().unwrap().push(progress.name.clone());
+    }
+
+    fn route(&self, route: &UpdateRoute) {
+        self.routes.lock().unwrap().push(route.describe());
+    }
+}
+
+fn use_core(
+    server: &TestServer,
+    key: &MinisignKey,
+    download_dir: &Path,
+    sink: &Arc<RecordingSink>,
+    answers: &[(PromptKey, bool)],
+) {
+    set_core(Core {
+        endpoints: Endpoints {
+            updates_url: format!("{}/manifest.json", server.url),
+            api_url: format!("{}/2", server.url),
+            dropbox_token: "test".to_string(),
+            public_key: key.public_key(),
+        },
+        download_dir: download_dir.to_path_buf(),
+        sink: sink.clone(),
+        prompter: Arc::new(HeadlessPrompter::new(answers.iter().copied().collect())),
+    });
+}
+
+async fn plan(install_dir: &Path) -> UpdatePlan {
+    check_updates(install_dir.to_str().unwrap()).await.unwrap()
+}
+
+// A single test, since the core is shared by the whole process
+#[tokio::test]
+async fn installs_and_updates_from_a_local_server() {
+    let temp = tempfile::tempdir().unwrap();
+    let install_dir = temp.path().join("game");
+    let download_dir = temp.path().join("downloads");
+    fs::create_dir_all(&install_dir).unwrap();
+    let key = MinisignKey::new();
+    let server = TestServer::start();
+    let sink = Arc::new(RecordingSink::default());
+
+    let regulation = vec![1u8; 512 * 1024];
+    server.publish(
+        "/ConvergenceER.zip",
+        zip(&[
+            ("mod/regulation.bin", &regulation),
+            ("mod/config.ini", b"a=1\n"),
+            ("version.txt", b"v1.0\n"),
+        ]),
+    );
+    server.publish_manifest(&key, "v1.0", None);
+
+    // Declining the full install leaves the directory alone
+    use_core(&server, &key, &download_dir, &sink, &[]);
+    let not_installed = plan(&install_dir).await;
+    assert_eq!(not_installed.status, UpdateStatus::NotInstalled);
+    assert!(
+        !apply_update_plan(not_installed, install_dir.to_str().unwrap())
+            .await
+            .unwrap()
+    );
+    assert!(!install_dir.join("mod").exists());
+
+    use_core(
+        &server,
+        &key,
+        &download_dir,
+        &sink,
+        &[(PromptKey::FullInstall, true)],
+    );
+    let not_installed = plan(&install_dir).await;
+    assert!(
+        apply_update_plan(not_installed, install_dir.to_str().unwrap())
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        fs::read(install_dir.join("mod/regulation.bin")).unwrap(),
+        regulation
+    );
+    assert!(!download_dir.join("Convergence.zip").exists());
+    let installed = plan(&install_dir).await;
+    assert_eq!(installed.status, UpdateStatus::UpToDate);
+    assert_eq!(installed.current.unwrap().to_string(), "v1.0");
+
+    let update = zip(&[("mod/extra.bin", b"extra"), ("mod/config.ini", b"a=2\n")]);
+    server.publish_manifest(&key, "v1.1", Some(&update));
+    let available = plan(&install_dir).await;
+    assert_eq!(available.status, UpdateStatus::Available);
+    assert!(apply_update_plan(available, install_dir.to_str().unwrap())
+        .await
+        .unwrap());
+    assert_eq!(
+        fs::read_to_string(install_dir.join("mod/extra.bin")).unwrap(),
+        "extra"
+    );
+    assert_eq!(
+        fs::read_to_string(install_dir.join("mod/config.ini")).unwrap(),
+        "a=2\n"
+    );
+    assert!(!download_dir.join("updates/v1.1.zip").exists());
+    let updated = plan(&install_dir).await;
+    assert_eq!(updated.status, UpdateStatus::UpToDate);
+    assert_eq!(updated.current.unwrap().to_string(), "v1.1");
+
+    assert_eq!(sink.routes.lock().unwrap().len(), 1);
+    assert!(sink
+        .progress
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|name| name == "All updates downloaded"));
+}
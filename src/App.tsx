@@ -13,6 +13,102 @@ interface ProgressEventPayload {
   speed: string;
 }
 
+type ConflictPolicy = "keep" | "take_new" | "save_as_new";
+
+type Channel = "stable" | "beta" | "nightly";
+
+interface Settings {
+  backup_retention: number;
+  conflict_policies: Record<string, ConflictPolicy>;
+  channel: Channel;
+}
+
+interface UpdateConflict {
+  path: string;
+  policy: ConflictPolicy;
+}
+
+interface UpdateArchive {
+  path: string;
+  size: number | null;
+  description: string | null;
+}
+
+interface UpdateRoute {
+  full: [string, UpdateArchive] | null;
+  updates: [string, UpdateArchive][];
+  total_size: number | null;
+}
+
+type UpdateStatus =
+  | "not_installed"
+  | "up_to_date"
+  | "available"
+  | "unsupported"
+  | "channel_switch"
+  | "ahead_of_manifest";
+
+interface PlannedArchive {
+  version: string;
+  path: string;
+  size: number | null;
+  description: string | null;
+  full: boolean;
+}
+
+interface UpdatePlan {
+  status: UpdateStatus;
+  current: string | null;
+  latest: string | null;
+  pinned: boolean;
+  least: string | null;
+  installed_channel: Channel | null;
+  channel: Channel;
+  archives: PlannedArchive[];
+  total_size: number | null;
+}
+
+type Prompt = {
+  id: number;
+  title: string;
+  message: string;
+} & (
+  | { kind: "notice" }
+  | { kind: "confirm"; key: string; ok: string; cancel: string }
+);
+
+function formatBytes(bytes: number | null): string {
+  return bytes === null
+    ? "size unknown"
+    : `${(bytes / 1024 / 1024).toFixed(1)} MB`;
+}
+
+function describePlan(plan: UpdatePlan): string {
+  switch (plan.status) {
+    case "not_installed":
+      return "Convergence is not installed in this directory";
+    case "up_to_date":
+      return `Up to date (${plan.current})`;
+    case "available":
+      return `Update ${plan.current} → ${plan.latest}: ${plan.archives.length} archive(s), ${formatBytes(plan.total_size)}`;
+    case "unsupported":
+      return `${plan.current} is too old to update; a fresh install of ${plan.latest} keeps your settings and saves`;
+    case "channel_switch":
+      return `Switching from ${plan.installed_channel} to ${plan.channel} reinstalls ${plan.latest}`;
+    case "ahead_of_manifest":
+      return plan.pinned
+        ? `${plan.current} is newer than the pinned version ${plan.latest}`
+        : `${plan.current} is newer than the latest release ${plan.latest}`;
+  }
+}
+
+function describeRoute(route: UpdateRoute): string {
+  const parts: string[] = [];
+  if (route.full) parts.push(`full install of ${route.full[0]}`);
+  if (route.updates.length > 0) parts.push(`${route.updates.length} update(s)`);
+  return `${parts.join(" + ")}, ${formatBytes(route.total_size)}`;
+}
+
 function App() {
   const [pathNotes, setPathNotes] = useState<string>("");
   const [installationDirectory, setInstallationDirectory] = useState("");
@@ -21,6 +117,12 @@ function App() {
   const [downloadStatus, setDownloadStatus] = useState<
     "idle" | "updating" | "downloading"
   >("idle");
+  const [paused, setPaused] = useState(false);
+  const [conflicts, setConflicts] = useState<UpdateConflict[]>([]);
+  const [route, setRoute] = useState<UpdateRoute>();
+  const [plan, setPlan] = useState<UpdatePlan>();
+  const [prompts, setPrompts] = useState<Prompt[]>([]);
+  const [settings, setSettings] = useState<Settings>();
   const [currentTab, setCurrentTab] = useState<"instructions" | "patch-notes">(
     "instructions"
   );
@@ -48,6 +150,57 @@ function App() {
     };
   }, []);
 
+  useEffect(() => {
+    const unListen = listen<UpdateConflict[]>("update_conflicts", (event) => {
+      setConflicts(event.payload);
+    });
+    return () => {
+      unListen.then((f) => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unListen = listen<Prompt>("prompt", (event) => {
+      setPrompts((current) => [...current, event.payload]);
+    });
+    return () => {
+      unListen.then((f) => f());
+    };
+  }, []);
+
+  const answerPrompt = async (prompt: Prompt, answer: boolean) => {
+    setPrompts((current) => current.filter((p) => p.id !== prompt.id));
+    if (prompt.kind === "notice") return;
+    try {
+      await invoke("answer_prompt", { id: prompt.id, answer });
+    } catch (error) {
+      console.error("Failed to answer prompt:", error);
+    }
+  };
+
+  useEffect(() => {
+    const unListen = listen<UpdateRoute>("update_route", (event) => {
+      setRoute(event.payload);
+    });
+    return () => {
+      unListen.then((f) => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    if (installationDirectory === "") return;
+    invoke<Settings>("get_settings", { directory: installationDirectory })
+      .then(setSettings)
+      .catch((error) => console.error("Failed to load settings:", error));
+  }, [installationDirectory]);
+
+  useEffect(() => {
+    if (installationDirectory === "" || downloadStatus !== "idle") return;
+    invoke<UpdatePlan>("get_update_plan", { directory: installationDirectory })
+      .then(setPlan)
+      .catch((error) => console.error("Failed to check for updates:", error));
+  }, [installationDirectory, settings?.channel, downloadStatus]);
+
   // --- Initial Actions ---
   useEffect(() => {
     // Check for updates once on component mount
@@ -102,6 +255,55 @@ function App() {
     }
   }
 
+  async function togglePause() {
+    try {
+      await invoke(paused ? "resume_download" : "pause_download");
+      setPaused(!paused);
+    } catch (error) {
+      console.error("Failed to pause/resume download:", error);
+    }
+  }
+
+  async function cancelDownload() {
+    try {
+      await invoke("cancel_download", { deletePartial: true });
+      setPaused(false);
+    } catch (error) {
+      console.error("Failed to cancel download:", error);
+    }
+  }
+
+  async function setConflictPolicy(path: string, policy: ConflictPolicy) {
+    try {
+      await invoke("set_conflict_policy", {
+        directory: installationDirectory,
+        path,
+        policy,
+      });
+      setConflicts(
+        conflicts.map((conflict) =>
+          conflict.path === path ? { ...conflict, policy } : conflict
+        )
+      );
+    } catch (error) {
+      console.error("Failed to save conflict policy:", error);
+    }
+  }
+
+  async function setChannel(channel: Channel) {
+    if (!settings) return;
+    const updated = { ...settings, channel };
+    try {
+      await invoke("save_settings", {
+        directory: installationDirectory,
+        settings: updated,
+      });
+      setSettings(updated);
+    } catch (error) {
+      console.error("Failed to save release channel:", error);
+    }
+  }
+
   async function getPatchNotes() {
     if (pathNotes !== "" && pathNotes !== "Fetching patch notes...") return; // Avoid re-fetching if already loaded or loading
     setPathNotes("Fetching patch notes...");
@@ -119,6 +321,7 @@ function App() {
 
   async function checkUpdates() {
     setProgressMessage("Checking for updates...");
+    setConflicts([]);
     try {
 
       setDownloadStatus("downloading");
@@ -126,10 +329,11 @@ function App() {
         downloading: true,
         directory: installationDirectory,
       });
-      if (result) {
-        setDownloadStatus("idle");
-        // await download();
+      if (!result) {
+        setProgressMessage("Update cancelled");
       }
+      // Back to idle either way, so a cancelled update shows the conflict panel
+      setDownloadStatus("idle");
     } catch (error) {
       console.error("Failed to check for updates");
       await message(`Failed to check for updates.${error}`, {
@@ -264,6 +468,28 @@ function App() {
                 style={{ width: progress?.progress ?? "0%" }} // Use progress state
               ></div>
             </div>
+            {/* Download Controls */}
+            <div className="flex justify-end gap-2 mt-2">
+              <button
+                type="button"
+                onClick={() => void togglePause()}
+                className="px-3 py-1 bg-slate-600 text-slate-200 rounded text-xs font-medium hover:bg-slate-500"
+              >
+                {paused ? "Resume" : "Pause"}
+              </button>
+              <button
+                type="button"
+                onClick={() => void cancelDownload()}
+                className="px-3 py-1 bg-red-700 text-slate-200 rounded text-xs font-medium hover:bg-red-600"
+              >
+                Cancel
+              </button>
+            </div>
+            {route && (
+              <div className="mt-1 text-xs text-slate-400">
+                Plan: {describeRoute(route)}
+              </div>
+            )}
             {/* Speed/Size Info */}
             <div className="flex justify-between mt-1 text-xs text-slate-400">
               <span hidden={progress?.speed === "N/A"}>
@@ -278,6 +504,78 @@ function App() {
             </div>
           </div>
         )}
+        {/* Modified Files: how each one is handled on the next update */}
+        {conflicts.length > 0 && downloadStatus === "idle" && (
+          <div className="mb-4 text-sm">
+            <div className="font-medium text-slate-200 mb-1">
+              Files you changed since they were installed
+            </div>
+            {conflicts.map((conflict) => (
+              <div
+                key={conflict.path}
+                className="flex justify-between items-center gap-2 mb-1"
+              >
+                <span className="truncate text-slate-300">{conflict.path}</span>
+                <select
+                  value={conflict.policy}
+                  onChange={(e) =>
+                    void setConflictPolicy(
+                      conflict.path,
+                      e.target.value as ConflictPolicy
+                    )
+                  }
+                  className="px-2 py-1 bg-slate-700 border border-slate-600 rounded text-slate-200 text-xs"
+                >
+                  <option value="keep">Keep my copy</option>
+                  <option value="take_new">Use the new file</option>
+                  <option value="save_as_new">Keep mine, save new as .new</option>
+                </select>
+              </div>
+            ))}
+          </div>
+        )}
+        {/* Questions and messages from the backend, oldest first */}
+        {prompts.length > 0 && (
+          <div className="mb-4 p-3 bg-slate-700 rounded text-sm">
+            <div className="font-medium text-slate-200 mb-1">
+              {prompts[0].title}
+            </div>
+            <div className="whitespace-pre-line text-slate-300 mb-2">
+              {prompts[0].message}
+            </div>
+            <div className="flex justify-end gap-2">
+              {prompts[0].kind === "confirm" ? (
+                <>
+                  <button
+                    type="button"
+                    onClick={() => void answerPrompt(prompts[0], false)}
+                    className="px-3 py-1 bg-slate-600 text-slate-200 rounded text-xs font-medium hover:bg-slate-500"
+                  >
+                    {prompts[0].cancel}
+                  </button>
+                  <button
+                    type="button"
+                    onClick={() => void answerPrompt(prompts[0], true)}
+                    className="px-3 py-1 bg-indigo-600 text-slate-200 rounded text-xs font-medium hover:bg-indigo-500"
+                  >
+                    {prompts[0].ok}
+                  </button>
+                </>
+              ) : (
+                <button
+                  type="button"
+                  onClick={() => void answerPrompt(prompts[0], true)}
+                  className="px-3 py-1 bg-slate-600 text-slate-200 rounded text-xs font-medium hover:bg-slate-500"
+                >
+                  OK
+                </button>
+              )}
+            </div>
+          </div>
+        )}
+        {plan && downloadStatus === "idle" && (
+          <div className="mb-4 text-sm text-slate-300">{describePlan(plan)}</div>
+        )}
         {/* Action Form */}
         <form
           onSubmit={(e) => {
@@ -327,6 +625,26 @@ function App() {
             </button>
           </div>
 
+          {/* Release Channel */}
+          {settings && (
+            <div className="flex gap-2 items-center text-sm">
+              <label htmlFor="channel" className="text-slate-300">
+                Release channel
+              </label>
+              <select
+                id="channel"
+                value={settings.channel}
+                onChange={(e) => void setChannel(e.target.value as Channel)}
+                disabled={downloadStatus !== "idle"}
+                className="px-2 py-1 bg-slate-700 border border-slate-600 rounded text-slate-200 text-xs disabled:opacity-50"
+              >
+                <option value="stable">Stable</option>
+                <option value="beta">Beta</option>
+                <option value="nightly">Nightly</option>
+              </select>
+            </div>
+          )}
+
           {/* Primary and Secondary Actions */}
           <div className="flex items-center gap-2">
             {/* Download Button (Primary) */}